@@ -0,0 +1,46 @@
+//! Latency of running a script that is already built
+//!
+//! `-v` bypasses the fingerprint, going through cargo's freshness checks instead.
+
+const ITERATIONS: usize = 50;
+
+fn main() {
+    let target_dir = std::env::temp_dir().join(format!("cargo-eval-bench-{}", std::process::id()));
+    let data = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+
+    for script in ["hello_world.rs", "path_dep.rs"] {
+        let script = data.join(script);
+        for (name, args) in [("fresh", &[][..]), ("through cargo", &["-v"][..])] {
+            // Warm up, building the script
+            run(&target_dir, args, &script);
+            let mut samples = (0..ITERATIONS)
+                .map(|_| {
+                    let start = std::time::Instant::now();
+                    run(&target_dir, args, &script);
+                    start.elapsed()
+                })
+                .collect::<Vec<_>>();
+            samples.sort();
+            let median = samples[samples.len() / 2];
+            let mean = samples.iter().sum::<std::time::Duration>() / samples.len() as u32;
+            println!(
+                "{:<16} {name:<16} median {median:>10.2?}  mean {mean:>10.2?}",
+                script.file_name().unwrap().to_string_lossy()
+            );
+        }
+    }
+
+    let _ = std::fs::remove_dir_all(&target_dir);
+}
+
+fn run(target_dir: &std::path::Path, args: &[&str], script: &std::path::Path) {
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_cargo-eval"))
+        .env("CARGO_TARGET_DIR", target_dir)
+        .args(args)
+        .arg(script)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
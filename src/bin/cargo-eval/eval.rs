@@ -18,22 +18,98 @@ pub fn cli() -> clap::Command {
                 .trailing_var_arg(true)
                 .value_parser(clap::value_parser!(OsString))
                 .help("Script file or expression to execute"),
+            clap::Arg::new("expr")
+                .short('e')
+                .long("expr")
+                .action(clap::ArgAction::SetTrue)
+                .help("Evaluate an expression and print its value (unstable)")
+                .requires("script")
+                .conflicts_with("action"),
+            clap::Arg::new("loop")
+                .short('l')
+                .long("loop")
+                .action(clap::ArgAction::SetTrue)
+                .help("Run a closure of the form `|line: &str, line_number: usize|` for each line of stdin (unstable)")
+                .requires("script")
+                .conflicts_with_all(["action", "expr"]),
+            clap::Arg::new("begin")
+                .long("begin")
+                .value_name("CODE")
+                .help("Code to run before `--loop`, e.g. to declare variables (unstable)")
+                .requires("loop"),
+            clap::Arg::new("end")
+                .long("end")
+                .value_name("CODE")
+                .help("Code to run after `--loop` (unstable)")
+                .requires("loop"),
+            clap::Arg::new("dep")
+                .short('d')
+                .long("dep")
+                .value_name("NAME[=VERSION]")
+                .action(clap::ArgAction::Append)
+                .help("Add a dependency to `--expr` or `--loop` (unstable)")
+                .requires("inline"),
             clap::Arg::new("release")
                 .short('r')
                 .long("release")
                 .action(clap::ArgAction::SetTrue)
                 .help("Build a release executable, an optimised one")
-                .conflicts_with_all(["bench"]),
+                .conflicts_with_all(["bench", "profile"]),
+            clap::Arg::new("profile")
+                .long("profile")
+                .value_name("PROFILE-NAME")
+                .help("Build with the given profile"),
+            clap::Arg::new("target")
+                .long("target")
+                .value_name("TRIPLE")
+                .help("Build for the target triple"),
+            clap::Arg::new("features")
+                .short('F')
+                .long("features")
+                .value_name("FEATURES")
+                .action(clap::ArgAction::Append)
+                .help("Space or comma separated list of features to activate"),
+            clap::Arg::new("all-features")
+                .long("all-features")
+                .action(clap::ArgAction::SetTrue)
+                .help("Activate all available features"),
+            clap::Arg::new("no-default-features")
+                .long("no-default-features")
+                .action(clap::ArgAction::SetTrue)
+                .help("Do not activate the `default` feature"),
             clap::Arg::new("target-dir")
                 .long("target-dir")
                 .value_name("DIRECTORY")
+                .value_parser(clap::value_parser!(PathBuf))
                 .help("Directory for all generated artifacts"),
+            clap::Arg::new("locked")
+                .long("locked")
+                .action(clap::ArgAction::SetTrue)
+                .help("Require the script's Cargo.lock to be up-to-date"),
+            clap::Arg::new("frozen")
+                .long("frozen")
+                .action(clap::ArgAction::SetTrue)
+                .help("Require the script's Cargo.lock and cache to be up-to-date"),
+            clap::Arg::new("offline")
+                .long("offline")
+                .action(clap::ArgAction::SetTrue)
+                .help("Run without accessing the network"),
             // Options that impact the script being executed.
             clap::Arg::new("verbose")
                 .short('v')
                 .long("verbose")
                 .action(clap::ArgAction::Count)
                 .help("Use verbose output"),
+            clap::Arg::new("color")
+                .long("color")
+                .value_name("WHEN")
+                .value_parser(["auto", "always", "never"])
+                .help("Coloring: auto, always, never"),
+            clap::Arg::new("config")
+                .long("config")
+                .value_name("KEY=VALUE")
+                .action(clap::ArgAction::Append)
+                .help("Override a configuration value"),
         ])
         // Options that change how rust-script itself behaves, and don't alter what the script will do.
         .args([
@@ -44,43 +120,234 @@ pub fn cli() -> clap::Command {
                 .action(clap::ArgAction::SetTrue)
                 .requires("script")
                 .group("action"),
+            clap::Arg::new("manifest")
+                .long("manifest")
+                .action(clap::ArgAction::SetTrue)
+                .help("Print the manifest cargo sees for the script (unstable)")
+                .help_heading("Polyfill")
+                .requires("script")
+                .group("action"),
+            clap::Arg::new("format")
+                .long("format")
+                .value_name("FMT")
+                .value_parser(clap::value_parser!(cargo_eval::ops::ManifestFormat))
+                .default_value("toml")
+                .help("Format for `--manifest`")
+                .help_heading("Polyfill")
+                .requires("manifest"),
             clap::Arg::new("test")
                 .long("test")
                 .action(clap::ArgAction::SetTrue)
                 .help("Compile and run tests (unstable)")
                 .help_heading("Polyfill")
                 .requires("script")
-                .group("action"),
+                .group("action")
+                .group("harness"),
             clap::Arg::new("bench")
                 .long("bench")
                 .action(clap::ArgAction::SetTrue)
                 .help("Compile and run benchmarks (unstable)")
                 .help_heading("Polyfill")
                 .requires("script")
-                .group("action"),
+                .group("action")
+                .group("harness"),
+            clap::Arg::new("no-run")
+                .long("no-run")
+                .action(clap::ArgAction::SetTrue)
+                .help("Compile, but don't run tests or benchmarks (unstable)")
+                .help_heading("Polyfill")
+                .requires("harness"),
+            clap::Arg::new("no-fail-fast")
+                .long("no-fail-fast")
+                .action(clap::ArgAction::SetTrue)
+                .help("Run all tests regardless of failure (unstable)")
+                .help_heading("Polyfill")
+                .requires("harness"),
+            clap::Arg::new("doc")
+                .long("doc")
+                .action(clap::ArgAction::SetTrue)
+                .help("Only run a library script's doc-tests (unstable)")
+                .help_heading("Polyfill")
+                .requires("test"),
             clap::Arg::new("unstable_flags")
                 .short('Z')
                 .value_name("FLAG")
                 .value_parser(clap::value_parser!(UnstableFlags))
                 .action(clap::ArgAction::Append)
+                .global(true)
                 .help("Unstable (nightly-only) flags"),
         ])
+        .group(clap::ArgGroup::new("inline").args(["expr", "loop"]))
+        .subcommands([add_cli(), remove_cli(), gc_cli()])
+}
+
+fn add_cli() -> clap::Command {
+    clap::Command::new("add")
+        .about("Add dependencies to a script's manifest (unstable)")
+        .args([
+            clap::Arg::new("script")
+                .value_name("PATH_RS")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("Script to edit"),
+            clap::Arg::new("crates")
+                .value_name("DEP_ID")
+                .num_args(0..)
+                .help("Reference to a package to add as a dependency")
+                .long_help(
+                    "Reference to a package to add as a dependency
+
+You can reference a package by:
+- `<name>`, like `cargo-eval add script.rs serde` (latest version will be used)
+- `<name>@<version-req>`, like `cargo-eval add script.rs serde@1` or `cargo-eval add script.rs serde@=1.0.38`",
+                )
+                .required_unless_present("path"),
+            clap::Arg::new("features")
+                .short('F')
+                .long("features")
+                .value_name("FEATURES")
+                .action(clap::ArgAction::Append)
+                .help("Space or comma separated list of features to activate"),
+            clap::Arg::new("default-features")
+                .long("default-features")
+                .action(clap::ArgAction::SetTrue)
+                .overrides_with("no-default-features")
+                .help("Re-enable the default features"),
+            clap::Arg::new("no-default-features")
+                .long("no-default-features")
+                .action(clap::ArgAction::SetTrue)
+                .overrides_with("default-features")
+                .help("Disable the default features"),
+            clap::Arg::new("optional")
+                .long("optional")
+                .action(clap::ArgAction::SetTrue)
+                .overrides_with("no-optional")
+                .help("Mark the dependency as optional"),
+            clap::Arg::new("no-optional")
+                .long("no-optional")
+                .action(clap::ArgAction::SetTrue)
+                .overrides_with("optional")
+                .help("Mark the dependency as required"),
+            clap::Arg::new("rename")
+                .long("rename")
+                .value_name("NAME")
+                .help("Rename the dependency"),
+            clap::Arg::new("path")
+                .long("path")
+                .value_name("PATH")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Filesystem path to local crate to add"),
+            clap::Arg::new("dry-run")
+                .long("dry-run")
+                .action(clap::ArgAction::SetTrue)
+                .help("Don't actually write the manifest"),
+        ])
+        .args(section_args("Add as"))
+}
+
+fn remove_cli() -> clap::Command {
+    clap::Command::new("remove")
+        .about("Remove dependencies from a script's manifest (unstable)")
+        .args([
+            clap::Arg::new("script")
+                .value_name("PATH_RS")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("Script to edit"),
+            clap::Arg::new("dependencies")
+                .value_name("DEP_ID")
+                .num_args(1..)
+                .required(true)
+                .help("Dependencies to be removed"),
+            clap::Arg::new("dry-run")
+                .long("dry-run")
+                .action(clap::ArgAction::SetTrue)
+                .help("Don't actually write the manifest"),
+        ])
+        .args(section_args("Remove as"))
+}
+
+fn gc_cli() -> clap::Command {
+    clap::Command::new("gc")
+        .about("Remove cached builds of old scripts (unstable)")
+        .long_about(
+            "Remove cached builds of old scripts (unstable)
+
+Without a policy, uses `eval.gc` from cargo's config, defaulting to `--missing-source --max-age 30d`",
+        )
+        .args([
+            clap::Arg::new("max-age")
+                .long("max-age")
+                .value_name("DURATION")
+                .value_parser(cargo_eval::util::gc::parse_duration)
+                .help("Remove scripts not run within this long, like `30d` or `12h`"),
+            clap::Arg::new("max-size")
+                .long("max-size")
+                .value_name("SIZE")
+                .value_parser(cargo_eval::util::gc::parse_size)
+                .help("Remove the least recently run scripts until the cache fits, like `2G`"),
+            clap::Arg::new("missing-source")
+                .long("missing-source")
+                .action(clap::ArgAction::SetTrue)
+                .help("Remove scripts that were deleted"),
+            clap::Arg::new("dry-run")
+                .long("dry-run")
+                .action(clap::ArgAction::SetTrue)
+                .help("Don't actually remove anything"),
+        ])
+}
+
+fn section_args(verb: &str) -> [clap::Arg; 2] {
+    [
+        clap::Arg::new("dev")
+            .long("dev")
+            .action(clap::ArgAction::SetTrue)
+            .conflicts_with("build")
+            .help(format!("{verb} development dependency")),
+        clap::Arg::new("build")
+            .long("build")
+            .action(clap::ArgAction::SetTrue)
+            .help(format!("{verb} build dependency")),
+    ]
 }
 
 pub fn exec(matches: &clap::ArgMatches, config: &mut cargo::util::Config) -> CliResult {
+    // `-Z` is global, so look for it where it is most complete
     let unstable_flags = matches
+        .subcommand()
+        .map(|(_, sub_matches)| sub_matches)
+        .unwrap_or(matches)
         .get_many::<UnstableFlags>("unstable_flags")
         .unwrap_or_default()
         .copied()
         .collect::<Vec<_>>();
 
-    let action = if matches.get_flag("clean") {
+    let action = if let Some((name, sub_matches)) = matches.subcommand() {
+        if !unstable_flags.contains(&UnstableFlags::Polyfill) {
+            return Err(
+                anyhow::format_err!("`{name}` is unstable and requires `-Zpolyfill`").into(),
+            );
+        }
+        match name {
+            "add" => Action::Add(sub_matches),
+            "remove" => Action::Remove(sub_matches),
+            "gc" => Action::Gc(sub_matches),
+            _ => unreachable!("clap verified the subcommand"),
+        }
+    } else if matches.get_flag("clean") {
         if !unstable_flags.contains(&UnstableFlags::Polyfill) {
             return Err(
                 anyhow::format_err!("`--clean` is unstable and requires `-Zpolyfill`").into(),
             );
         }
         Action::Clean
+    } else if matches.get_flag("manifest") {
+        if !unstable_flags.contains(&UnstableFlags::Polyfill) {
+            return Err(
+                anyhow::format_err!("`--manifest` is unstable and requires `-Zpolyfill`").into(),
+            );
+        }
+        Action::Manifest
     } else if matches.get_flag("test") {
         if !unstable_flags.contains(&UnstableFlags::Polyfill) {
             return Err(
@@ -99,24 +366,29 @@ pub fn exec(matches: &clap::ArgMatches, config: &mut cargo::util::Config) -> Cli
         Action::Run
     };
 
-    let mut script_and_args = matches
-        .get_many::<OsString>("script")
-        .unwrap_or_default()
-        .cloned();
-    let script = script_and_args.next();
-    let script = if let Some(script) = script {
-        script
+    if matches.get_flag("expr") && !unstable_flags.contains(&UnstableFlags::Eval) {
+        return Err(anyhow::format_err!("`--expr` is unstable and requires `-Zeval`").into());
+    }
+    if matches.get_flag("loop") && !unstable_flags.contains(&UnstableFlags::Loop) {
+        return Err(anyhow::format_err!("`--loop` is unstable and requires `-Zloop`").into());
+    }
+
+    let profile = if matches.get_flag("release") {
+        Some("release".to_owned())
     } else {
-        use is_terminal::IsTerminal;
-        if std::io::stdin().is_terminal() {
-            return Err(anyhow::format_err!("<PATH_RS> is required").into());
-        } else {
-            "-".into()
-        }
+        matches.get_one::<String>("profile").cloned()
+    };
+    let compile_args = cargo_eval::ops::CompileArgs {
+        profile,
+        target: matches.get_one::<String>("target").cloned(),
+        features: matches
+            .get_many::<String>("features")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        all_features: matches.get_flag("all-features"),
+        no_default_features: matches.get_flag("no-default-features"),
     };
-    let script_args: Vec<OsString> = script_and_args.collect();
-
-    let release = matches.get_flag("release");
 
     let verbose = matches.get_count("verbose");
     let (verbose, quiet) = if matches!(&action, Action::Run) {
@@ -127,21 +399,16 @@ pub fn exec(matches: &clap::ArgMatches, config: &mut cargo::util::Config) -> Cli
     } else {
         (verbose, false)
     };
-    let color = None;
-    let frozen = false;
-    let locked = false;
-    let offline = false;
-    // HACK: We should only pass in `--target-dir` to config **but** we need to make sure that
-    // `default_target_dir` is used instead of one derived from the `Workspace`s location.  If/when
-    // upstreamed into cargo, instead `Workspace` would recognize that its using an embedded
-    // manifest and would instead choose `default_target_dir` for us.
-    let target_dir = matches
-        .get_one::<PathBuf>("target-dir")
+    let frozen = matches.get_flag("frozen");
+    let locked = matches.get_flag("locked");
+    let offline = matches.get_flag("offline");
+    let color = matches.get_one::<String>("color").map(String::as_str);
+    let target_dir = matches.get_one::<PathBuf>("target-dir").cloned();
+    let cli_config = matches
+        .get_many::<String>("config")
+        .unwrap_or_default()
         .cloned()
-        .or_else(|| std::env::var_os("CARGO_TARGET_DIR").map(PathBuf::from))
-        .map(Ok)
-        .unwrap_or_else(cargo_eval::config::default_target_dir)?;
-    let cli_config = [];
+        .collect::<Vec<_>>();
     config.configure(
         verbose as u32,
         quiet,
@@ -149,17 +416,58 @@ pub fn exec(matches: &clap::ArgMatches, config: &mut cargo::util::Config) -> Cli
         frozen,
         locked,
         offline,
-        &Some(target_dir),
+        &target_dir,
         &[],
         &cli_config,
     )?;
 
+    if let Action::Run = action {
+        if let Some(fresh) = fresh_process(config, matches, compile_args.profile.as_deref())? {
+            if std::env::var_os("RUST_BACKTRACE").is_none() {
+                std::env::set_var("RUST_BACKTRACE", "1");
+            }
+            config
+                .shell()
+                .set_verbosity(cargo::core::shell::Verbosity::Quiet);
+            let target_dir = cargo::util::Filesystem::new(fresh.target_dir);
+            fresh.script.record_fresh_use(config, &target_dir)?;
+            if let Err(err) = cargo_eval::util::gc::auto(config, &target_dir, &fresh.script) {
+                config
+                    .shell()
+                    .warn(format!("failed to collect stale scripts: {err:#}"))?;
+            }
+            return cargo_eval::util::exec_replace(&fresh.process, fresh.arg0.as_deref())
+                .map_err(|err| to_run_error(config, err));
+        }
+    }
+
     match action {
         Action::Run => {
+            let (script, script_args) = script_and_args(matches)?;
             if std::env::var_os("RUST_BACKTRACE").is_none() {
                 std::env::set_var("RUST_BACKTRACE", "1");
             }
-            let manifest_path = if script == "-" {
+            let deps = matches
+                .get_many::<String>("dep")
+                .unwrap_or_default()
+                .map(String::as_str);
+            let from_stdin =
+                !matches.get_flag("expr") && !matches.get_flag("loop") && script == "-";
+            let manifest_path = if matches.get_flag("expr") {
+                let expr = script
+                    .to_str()
+                    .ok_or_else(|| anyhow::format_err!("expression is not valid UTF-8"))?;
+                let main = expr_script(expr, deps)?;
+                temp_script(config, &main, "expr")?
+            } else if matches.get_flag("loop") {
+                let closure = script
+                    .to_str()
+                    .ok_or_else(|| anyhow::format_err!("closure is not valid UTF-8"))?;
+                let begin = matches.get_one::<String>("begin").map(String::as_str);
+                let end = matches.get_one::<String>("end").map(String::as_str);
+                let main = loop_script(closure, begin, end, deps)?;
+                temp_script(config, &main, "loop")?
+            } else if from_stdin {
                 use std::io::Read as _;
                 let mut main = String::new();
                 std::io::stdin().read_to_string(&mut main)?;
@@ -167,30 +475,414 @@ pub fn exec(matches: &clap::ArgMatches, config: &mut cargo::util::Config) -> Cli
             } else {
                 dunce::canonicalize(PathBuf::from(script))?
             };
-            cargo_eval::ops::run(config, &manifest_path, &script_args, release)
-                .map_err(|err| to_run_error(config, err))?;
+            cargo_eval::ops::run(
+                config,
+                &manifest_path,
+                &script_args,
+                &compile_args,
+                from_stdin,
+            )
+            .map_err(|err| to_run_error(config, err))?;
         }
         Action::Clean => {
+            let (script, _) = script_and_args(matches)?;
             let manifest_path = dunce::canonicalize(PathBuf::from(script))?;
-            cargo_eval::ops::clean(config, &manifest_path)?;
+            cargo_eval::ops::clean(config, &manifest_path, &compile_args)?;
+        }
+        Action::Manifest => {
+            let (script, _) = script_and_args(matches)?;
+            let manifest_path = dunce::canonicalize(PathBuf::from(script))?;
+            let format = *matches
+                .get_one::<cargo_eval::ops::ManifestFormat>("format")
+                .unwrap();
+            cargo_eval::ops::manifest(config, &manifest_path, format)?;
         }
         Action::Test => {
+            let (script, script_args) = script_and_args(matches)?;
             let manifest_path = dunce::canonicalize(PathBuf::from(script))?;
-            cargo_eval::ops::test(config, &manifest_path)?;
+            let harness_args = harness_args(matches, script_args)?;
+            cargo_eval::ops::test(config, &manifest_path, &compile_args, &harness_args)?;
         }
         Action::Bench => {
+            let (script, script_args) = script_and_args(matches)?;
             let manifest_path = dunce::canonicalize(PathBuf::from(script))?;
-            cargo_eval::ops::bench(config, &manifest_path)?;
+            let harness_args = harness_args(matches, script_args)?;
+            cargo_eval::ops::bench(config, &manifest_path, &compile_args, &harness_args)?;
+        }
+        Action::Add(matches) => {
+            let manifest_path = dunce::canonicalize(matches.get_one::<PathBuf>("script").unwrap())?;
+            let dependencies = add_dependencies(matches)?;
+            let options = cargo_eval::ops::AddOptions {
+                config,
+                dependencies,
+                section: dep_table(matches),
+                dry_run: matches.get_flag("dry-run"),
+            };
+            cargo_eval::ops::add(&manifest_path, &options)?;
+        }
+        Action::Remove(matches) => {
+            let manifest_path = dunce::canonicalize(matches.get_one::<PathBuf>("script").unwrap())?;
+            let options = cargo_eval::ops::RemoveOptions {
+                config,
+                dependencies: matches
+                    .get_many::<String>("dependencies")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+                section: dep_table(matches),
+                dry_run: matches.get_flag("dry-run"),
+            };
+            cargo_eval::ops::remove(&manifest_path, &options)?;
+        }
+        Action::Gc(matches) => {
+            let policy = cargo_eval::util::gc::GcPolicy {
+                max_age: matches.get_one("max-age").copied(),
+                max_size: matches.get_one("max-size").copied(),
+                missing_source: matches.get_flag("missing-source"),
+            };
+            let options = cargo_eval::ops::GcOptions {
+                config,
+                policy: (policy != Default::default()).then_some(policy),
+                dry_run: matches.get_flag("dry-run"),
+            };
+            cargo_eval::ops::gc(&options)?;
         }
     }
 
     Ok(())
 }
 
+fn script_and_args(matches: &clap::ArgMatches) -> CargoResult<(OsString, Vec<OsString>)> {
+    let mut script_and_args = matches
+        .get_many::<OsString>("script")
+        .unwrap_or_default()
+        .cloned();
+    let script = script_and_args.next();
+    let script = if let Some(script) = script {
+        script
+    } else {
+        use is_terminal::IsTerminal;
+        if std::io::stdin().is_terminal() {
+            anyhow::bail!("<PATH_RS> is required");
+        } else {
+            "-".into()
+        }
+    };
+    let script_args: Vec<OsString> = script_and_args.collect();
+    Ok((script, script_args))
+}
+
+fn harness_args(
+    matches: &clap::ArgMatches,
+    script_args: Vec<OsString>,
+) -> CargoResult<cargo_eval::ops::HarnessArgs> {
+    let args = script_args
+        .into_iter()
+        .map(|arg| {
+            arg.into_string().map_err(|arg| {
+                anyhow::format_err!(
+                    "harness argument `{}` is not valid UTF-8",
+                    arg.to_string_lossy()
+                )
+            })
+        })
+        .collect::<CargoResult<Vec<_>>>()?;
+    Ok(cargo_eval::ops::HarnessArgs {
+        no_run: matches.get_flag("no-run"),
+        no_fail_fast: matches.get_flag("no-fail-fast"),
+        doc: matches.get_flag("doc"),
+        args,
+    })
+}
+
+/// A script whose binary can run as-is
+struct FreshProcess {
+    process: cargo_util::ProcessBuilder,
+    arg0: Option<OsString>,
+    script: cargo_eval::util::script::RawScript,
+    target_dir: PathBuf,
+}
+
+/// The script's binary and its `argv[0]`, if nothing it was built from has changed, to skip
+/// loading cargo
+fn fresh_process(
+    config: &cargo::Config,
+    matches: &clap::ArgMatches,
+    profile: Option<&str>,
+) -> CargoResult<Option<FreshProcess>> {
+    // Leave anything that changes how the script is built, checks it, or reports on it, to cargo
+    let needs_cargo = matches.get_flag("expr")
+        || matches.get_flag("loop")
+        || matches.get_flag("all-features")
+        || matches.get_flag("no-default-features")
+        || matches.get_flag("locked")
+        || matches.get_flag("frozen")
+        || matches.get_count("verbose") != 0
+        || ["config", "target", "features"]
+            .into_iter()
+            .any(|id| matches.contains_id(id));
+    if needs_cargo {
+        return Ok(None);
+    }
+    let (script, script_args) = script_and_args(matches)?;
+    if script == "-" {
+        return Ok(None);
+    }
+    let script = match dunce::canonicalize(PathBuf::from(script))
+        .ok()
+        .and_then(|path| cargo_eval::util::script::RawScript::parse_from(&path).ok())
+    {
+        Some(script) => script,
+        None => return Ok(None),
+    };
+
+    let cwd = std::env::current_dir()?;
+    let target_dir = match script.target_dir(config) {
+        Ok(target_dir) => target_dir.into_path_unlocked(),
+        Err(_) => return Ok(None),
+    };
+    let profile = profile.unwrap_or("dev");
+    let (mut process, arg0) =
+        match cargo_eval::util::fingerprint::fresh_process(&script, &target_dir, profile) {
+            Some(fresh) => fresh,
+            None => return Ok(None),
+        };
+    process.args(&script_args).cwd(cwd);
+    Ok(Some(FreshProcess {
+        process,
+        arg0,
+        script,
+        target_dir,
+    }))
+}
+
+fn add_dependencies(matches: &clap::ArgMatches) -> CargoResult<Vec<cargo_eval::ops::DepOp>> {
+    let features = matches
+        .get_many::<String>("features")
+        .unwrap_or_default()
+        .flat_map(|f| f.split(|c: char| c == ',' || c.is_whitespace()))
+        .filter(|f| !f.is_empty())
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let default_features = flag(matches, "default-features", "no-default-features");
+    let optional = flag(matches, "optional", "no-optional");
+    let rename = matches.get_one::<String>("rename").cloned();
+    let path = matches.get_one::<PathBuf>("path").cloned();
+
+    let crates = matches
+        .get_many::<String>("crates")
+        .unwrap_or_default()
+        .map(|c| Some(c.clone()))
+        .collect::<Vec<_>>();
+    let crates = if crates.is_empty() {
+        vec![None]
+    } else {
+        crates
+    };
+    if 1 < crates.len() {
+        if rename.is_some() {
+            anyhow::bail!("cannot specify multiple crates with `--rename`");
+        }
+        if path.is_some() {
+            anyhow::bail!("cannot specify multiple crates with `--path`");
+        }
+        if !features.is_empty() {
+            anyhow::bail!("cannot specify multiple crates with `--features`");
+        }
+    }
+
+    Ok(crates
+        .into_iter()
+        .map(|crate_spec| cargo_eval::ops::DepOp {
+            crate_spec,
+            rename: rename.clone(),
+            features: features.clone(),
+            default_features,
+            optional,
+            path: path.clone(),
+        })
+        .collect())
+}
+
+fn flag(matches: &clap::ArgMatches, positive: &str, negative: &str) -> Option<bool> {
+    if matches.get_flag(positive) {
+        Some(true)
+    } else if matches.get_flag(negative) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn dep_table(matches: &clap::ArgMatches) -> cargo::util::toml_mut::manifest::DepTable {
+    let kind = if matches.get_flag("dev") {
+        cargo::core::dependency::DepKind::Development
+    } else if matches.get_flag("build") {
+        cargo::core::dependency::DepKind::Build
+    } else {
+        cargo::core::dependency::DepKind::Normal
+    };
+    cargo::util::toml_mut::manifest::DepTable::from(kind)
+}
+
+/// Wraps an expression in a script that prints its value
+fn expr_script<'d>(expr: &str, deps: impl Iterator<Item = &'d str>) -> CargoResult<String> {
+    let mut main = inline_manifest(deps)?;
+    main.push_str(
+        "fn main() {
+    let __cargo_eval_expr = {
+",
+    );
+    main.push_str(expr);
+    main.push_str(
+        "
+    };
+    println!(\"{:?}\", __cargo_eval_expr);
+}
+",
+    );
+    Ok(main)
+}
+
+/// Wraps a closure in a script that calls it for each line of stdin
+///
+/// `print!` and `println!` are shadowed to write to a buffered stdout.
+fn loop_script<'d>(
+    closure: &str,
+    begin: Option<&str>,
+    end: Option<&str>,
+    deps: impl Iterator<Item = &'d str>,
+) -> CargoResult<String> {
+    let mut main = inline_manifest(deps)?;
+    main.push_str(
+        "thread_local! {
+    static __CARGO_EVAL_STDOUT: std::cell::RefCell<std::io::BufWriter<std::io::Stdout>> =
+        std::cell::RefCell::new(std::io::BufWriter::new(std::io::stdout()));
+}
+
+#[allow(unused_macros)]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        __CARGO_EVAL_STDOUT.with(|stdout| {
+            use std::io::Write as _;
+            write!(stdout.borrow_mut(), $($arg)*).expect(\"failed to write to stdout\");
+        })
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! println {
+    ($($arg:tt)*) => {
+        __CARGO_EVAL_STDOUT.with(|stdout| {
+            use std::io::Write as _;
+            writeln!(stdout.borrow_mut(), $($arg)*).expect(\"failed to write to stdout\");
+        })
+    };
+}
+
+fn __cargo_eval_closure<F: FnMut(&str, usize) -> T, T>(closure: F) -> F {
+    closure
+}
+
+fn __cargo_eval_is_unit<T>(_: &T) -> bool {
+    std::any::type_name::<T>() == \"()\"
+}
+
+// Method resolution picks `Display` over `Debug`, as it needs one less auto-ref
+struct __CargoEvalOutput<T>(T);
+
+trait __CargoEvalDisplay {
+    fn __cargo_eval_format(&self) -> String;
+}
+
+impl<T: std::fmt::Display> __CargoEvalDisplay for __CargoEvalOutput<T> {
+    fn __cargo_eval_format(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+trait __CargoEvalDebug {
+    fn __cargo_eval_format(&self) -> String;
+}
+
+impl<T: std::fmt::Debug> __CargoEvalDebug for &__CargoEvalOutput<T> {
+    fn __cargo_eval_format(&self) -> String {
+        format!(\"{:?}\", self.0)
+    }
+}
+
+fn main() {
+",
+    );
+    if let Some(begin) = begin {
+        main.push_str(begin);
+        main.push('\n');
+    }
+    main.push_str(
+        "    {
+        use std::io::BufRead as _;
+        let mut __cargo_eval_closure = __cargo_eval_closure(
+",
+    );
+    main.push_str(closure);
+    main.push_str(
+        "
+        );
+        for (i, line) in std::io::stdin().lock().lines().enumerate() {
+            let line = line.expect(\"failed to read stdin\");
+            let output = __cargo_eval_closure(&line, i + 1);
+            if !__cargo_eval_is_unit(&output) {
+                println!(\"{}\", (&__CargoEvalOutput(output)).__cargo_eval_format());
+            }
+        }
+    }
+",
+    );
+    if let Some(end) = end {
+        main.push_str("    {\n");
+        main.push_str(end);
+        main.push_str("\n    }\n");
+    }
+    main.push_str(
+        "    __CARGO_EVAL_STDOUT.with(|stdout| {
+        use std::io::Write as _;
+        stdout.borrow_mut().flush().expect(\"failed to write to stdout\");
+    });
+}
+",
+    );
+    Ok(main)
+}
+
+/// Doc-comment manifest for `--dep name[=version]`s
+fn inline_manifest<'d>(deps: impl Iterator<Item = &'d str>) -> CargoResult<String> {
+    let mut dependencies = toml::Table::new();
+    for dep in deps {
+        let (name, version) = dep.split_once('=').unwrap_or((dep, "*"));
+        let name = name.trim();
+        if name.is_empty() {
+            anyhow::bail!("dependency name is missing from `--dep {dep}`");
+        }
+        dependencies.insert(name.to_owned(), version.trim().to_owned().into());
+    }
+
+    let mut manifest = String::new();
+    if !dependencies.is_empty() {
+        let mut table = toml::Table::new();
+        table.insert("dependencies".to_owned(), dependencies.into());
+        manifest.push_str("//! ```cargo\n");
+        for line in toml::to_string(&table)?.lines() {
+            manifest.push_str("//! ");
+            manifest.push_str(line);
+            manifest.push('\n');
+        }
+        manifest.push_str("//! ```\n");
+    }
+    Ok(manifest)
+}
+
 fn temp_script(config: &cargo::Config, main: &str, id: &str) -> CargoResult<PathBuf> {
-    let target_dir = config.target_dir().transpose().unwrap_or_else(|| {
-        cargo_eval::config::default_target_dir().map(cargo::util::Filesystem::new)
-    })?;
+    let target_dir = cargo_eval::config::target_dir(config)?;
     let hash = blake3::hash(main.as_bytes()).to_string();
     let mut main_path = target_dir.as_path_unlocked().to_owned();
     main_path.push("eval");
@@ -228,14 +920,38 @@ fn to_run_error(config: &cargo::util::Config, err: anyhow::Error) -> CliError {
     }
 }
 
-enum Action {
+enum Action<'m> {
     Run,
     Clean,
+    Manifest,
     Test,
     Bench,
+    Add(&'m clap::ArgMatches),
+    Remove(&'m clap::ArgMatches),
+    Gc(&'m clap::ArgMatches),
 }
 
 #[test]
 fn verify_cli() {
     cli().debug_assert()
 }
+
+#[test]
+fn verify_expr_script() {
+    snapbox::assert_eq(
+        r#"//! ```cargo
+//! [dependencies]
+//! regex = "1.7"
+//! time = "*"
+//! ```
+fn main() {
+    let __cargo_eval_expr = {
+1 + 2
+    };
+    println!("{:?}", __cargo_eval_expr);
+}
+"#,
+        expr_script("1 + 2", ["regex=1.7", "time"].into_iter()).unwrap(),
+    );
+    assert!(expr_script("1 + 2", ["=1.7"].into_iter()).is_err());
+}
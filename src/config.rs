@@ -13,3 +13,11 @@ pub fn default_target_dir() -> CargoResult<std::path::PathBuf> {
     cargo_home.push("target");
     Ok(cargo_home)
 }
+
+/// The target directory from cargo's config, defaulting to one shared by all scripts
+pub fn target_dir(config: &cargo::Config) -> CargoResult<cargo::util::Filesystem> {
+    config
+        .target_dir()
+        .transpose()
+        .unwrap_or_else(|| default_target_dir().map(cargo::util::Filesystem::new))
+}
@@ -0,0 +1,192 @@
+use cargo::core::registry::PackageRegistry;
+use cargo::core::QueryKind;
+use cargo::core::Registry as _;
+use cargo::util::toml_mut::dependency::Dependency;
+use cargo::util::toml_mut::dependency::MaybeWorkspace;
+use cargo::util::toml_mut::dependency::PathSource;
+use cargo::util::toml_mut::dependency::RegistrySource;
+use cargo::util::toml_mut::manifest::DepTable;
+
+use crate::CargoResult;
+
+/// Dependency to add or modify in a script's manifest
+#[derive(Clone, Debug, Default)]
+pub struct DepOp {
+    /// `name[@version]`, optional when `path` is set
+    pub crate_spec: Option<String>,
+    /// Dependency key, overriding the package name
+    pub rename: Option<String>,
+    /// Features to activate
+    pub features: Vec<String>,
+    pub default_features: Option<bool>,
+    pub optional: Option<bool>,
+    /// Local package, relative to the current directory
+    pub path: Option<std::path::PathBuf>,
+}
+
+pub struct AddOptions<'a> {
+    pub config: &'a cargo::Config,
+    pub dependencies: Vec<DepOp>,
+    pub section: DepTable,
+    pub dry_run: bool,
+}
+
+/// Add dependencies to a script's embedded manifest
+pub fn add(manifest_path: &std::path::Path, options: &AddOptions<'_>) -> CargoResult<()> {
+    let script = crate::util::script::RawScript::parse_from(manifest_path)?;
+    let mut manifest = super::local_manifest(&script)?;
+    let crate_root = manifest
+        .path
+        .parent()
+        .expect("manifest is always in a directory")
+        .to_owned();
+    let dep_table = options
+        .section
+        .to_table()
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    let mut registry = PackageRegistry::new(options.config)?;
+    let deps = {
+        let _lock = options.config.acquire_package_cache_lock()?;
+        registry.lock_patches();
+        options
+            .dependencies
+            .iter()
+            .map(|raw| {
+                resolve_dependency(
+                    &manifest,
+                    &dep_table,
+                    &crate_root,
+                    raw,
+                    options.config,
+                    &mut registry,
+                )
+            })
+            .collect::<CargoResult<Vec<_>>>()?
+    };
+
+    for dep in deps {
+        let version = match dep.source() {
+            Some(cargo::util::toml_mut::dependency::Source::Path(_)) => " (local)".to_owned(),
+            _ => dep.version().map(|v| format!(" v{v}")).unwrap_or_default(),
+        };
+        options.config.shell().status(
+            "Adding",
+            format!(
+                "{}{version} to {}",
+                dep.toml_key(),
+                super::section_name(&dep_table)
+            ),
+        )?;
+        manifest.insert_into_table(&dep_table, &dep)?;
+        manifest.gc_dep(dep.toml_key());
+    }
+
+    super::write_manifest(&script, &manifest, options.config, options.dry_run, "add")
+}
+
+fn resolve_dependency(
+    manifest: &cargo::util::toml_mut::manifest::LocalManifest,
+    dep_table: &[String],
+    crate_root: &std::path::Path,
+    raw: &DepOp,
+    config: &cargo::Config,
+    registry: &mut PackageRegistry<'_>,
+) -> CargoResult<Dependency> {
+    let (name, version) = match raw.crate_spec.as_deref() {
+        Some(spec) => {
+            let (name, version) = match spec.split_once('@') {
+                Some((name, version)) => (name, Some(version)),
+                None => (spec, None),
+            };
+            (Some(name.to_owned()), version)
+        }
+        None => (None, None),
+    };
+
+    let mut dep = if let Some(path) = raw.path.as_deref() {
+        let path = config.cwd().join(path);
+        let source_id = cargo::core::SourceId::for_path(&path)?;
+        let (package, _) = cargo::ops::read_package(&path.join("Cargo.toml"), source_id, config)?;
+        let package_name = package.name().as_str();
+        if name.as_deref().map(|n| n != package_name) == Some(true) {
+            anyhow::bail!(
+                "the crate `{}` could not be found at `{}`, found `{package_name}`",
+                name.unwrap(),
+                path.display()
+            );
+        }
+        let mut source = PathSource::new(path);
+        if let Some(version) = version {
+            source = source.set_version(version);
+        }
+        Dependency::new(package_name).set_source(source)
+    } else {
+        let name =
+            name.ok_or_else(|| anyhow::format_err!("a crate name or `--path` is required"))?;
+        let key = raw.rename.as_deref().unwrap_or(&name);
+        let existing = manifest
+            .get_table(dep_table)
+            .ok()
+            .and_then(|t| t.get(key))
+            .map(|item| Dependency::from_toml(crate_root, key, item))
+            .transpose()?
+            .filter(|existing| existing.name == name);
+        match (version, existing) {
+            (Some(version), _) => {
+                let dep = Dependency::new(&name).set_source(RegistrySource::new(version));
+                // Validate the version requirement
+                dep.query(config)?;
+                dep
+            }
+            (None, Some(existing)) => existing,
+            (None, None) => latest_dependency(&name, config, registry)?,
+        }
+    };
+
+    if let Some(rename) = raw.rename.as_deref() {
+        dep = dep.set_rename(rename);
+    }
+    if !raw.features.is_empty() {
+        dep = dep.extend_features(raw.features.iter().cloned());
+    }
+    if let Some(default_features) = raw.default_features {
+        dep = dep.set_default_features(default_features);
+    }
+    if let Some(optional) = raw.optional {
+        dep = dep.set_optional(optional);
+    }
+    Ok(dep)
+}
+
+fn latest_dependency(
+    name: &str,
+    config: &cargo::Config,
+    registry: &mut PackageRegistry<'_>,
+) -> CargoResult<Dependency> {
+    let query = match Dependency::new(name).query(config)? {
+        MaybeWorkspace::Other(query) => query,
+        MaybeWorkspace::Workspace(_) => {
+            unreachable!("registry dependencies required, found a workspace dependency")
+        }
+    };
+    let possibilities = loop {
+        match registry.query_vec(&query, QueryKind::Fuzzy) {
+            std::task::Poll::Ready(res) => break res?,
+            std::task::Poll::Pending => registry.block_until_ready()?,
+        }
+    };
+    let latest = possibilities
+        .iter()
+        .max_by_key(|s| {
+            // Fallback to a pre-release if no official release is available
+            let stable = s.version().pre.is_empty();
+            (stable, s.version())
+        })
+        .ok_or_else(|| {
+            anyhow::format_err!("the crate `{name}` could not be found in registry index.")
+        })?;
+    Ok(Dependency::from(latest.clone()))
+}
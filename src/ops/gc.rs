@@ -0,0 +1,59 @@
+use crate::util::gc::GcPolicy;
+use crate::CargoResult;
+
+pub struct GcOptions<'a> {
+    pub config: &'a cargo::Config,
+    /// Defaults to `eval.gc` in cargo's config
+    pub policy: Option<GcPolicy>,
+    pub dry_run: bool,
+}
+
+/// Remove the cached workspaces and binaries of scripts per a [`GcPolicy`], from the shared target
+/// directory and any others scripts were built into
+pub fn gc(options: &GcOptions<'_>) -> CargoResult<()> {
+    let config = options.config;
+    let policy = match options.policy.clone() {
+        Some(policy) => policy,
+        None => GcPolicy::from_config(config)?,
+    };
+
+    let mut collected = Vec::new();
+    let mut remaining = 0;
+    for target_dir in crate::util::gc::target_dirs(config)? {
+        let (scripts, size) =
+            crate::util::gc::collect(config, &target_dir, &policy, options.dry_run)?;
+        collected.extend(scripts);
+        remaining += size;
+    }
+    for script in &collected {
+        let (size, unit) = cargo::util::human_readable_bytes(script.entry.size);
+        config.shell().status(
+            "Removing",
+            format!("{} ({size:.1}{unit})", script.entry.source.display()),
+        )?;
+    }
+    let removed = collected.iter().map(|c| c.entry.size).sum::<u64>();
+    let (removed, removed_unit) = cargo::util::human_readable_bytes(removed);
+    let (remaining, remaining_unit) = cargo::util::human_readable_bytes(remaining);
+    let verb = if options.dry_run {
+        "Summary"
+    } else {
+        "Removed"
+    };
+    config.shell().status(
+        verb,
+        format!(
+            "{} {}, {removed:.1}{removed_unit} total ({remaining:.1}{remaining_unit} remaining)",
+            collected.len(),
+            if collected.len() == 1 {
+                "script"
+            } else {
+                "scripts"
+            },
+        ),
+    )?;
+    if options.dry_run {
+        config.shell().warn("aborting gc due to dry run")?;
+    }
+    Ok(())
+}
@@ -1,129 +1,406 @@
+mod add;
+mod gc;
+mod remove;
+
+pub use add::*;
+pub use gc::*;
+pub use remove::*;
+
+use anyhow::Context as _;
+
 use crate::CargoResult;
 use crate::CliResult;
 
+/// Compilation flags shared by all actions
+#[derive(Clone, Debug, Default)]
+pub struct CompileArgs {
+    /// Defaults to the action's profile, like `dev` for running
+    pub profile: Option<String>,
+    /// Target triple to build for, defaulting to the host
+    pub target: Option<String>,
+    pub features: Vec<String>,
+    pub all_features: bool,
+    pub no_default_features: bool,
+}
+
+/// How to drive the libtest harness for tests and benchmarks
+#[derive(Clone, Debug, Default)]
+pub struct HarnessArgs {
+    /// Compile but don't run
+    pub no_run: bool,
+    /// Run all tests, regardless of failure
+    pub no_fail_fast: bool,
+    /// Only run a library script's doc-tests
+    pub doc: bool,
+    /// Arguments for the harness, like name filters or `--nocapture`
+    pub args: Vec<String>,
+}
+
+/// Encoding for the expanded manifest
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ManifestFormat {
+    #[default]
+    Toml,
+    Json,
+}
+
+/// Build and run a script, with `from_stdin` for scripts written to `manifest_path` from stdin
 pub fn run(
     config: &cargo::Config,
     manifest_path: &std::path::Path,
     args: &[std::ffi::OsString],
-    release: bool,
+    compile_args: &CompileArgs,
+    from_stdin: bool,
 ) -> CargoResult<()> {
     let script = crate::util::script::RawScript::parse_from(manifest_path)?;
     let ws = script.to_workspace(config)?;
+    if script.is_lib() {
+        anyhow::bail!(
+            "`{}` is a library, so can't be run
+
+help: to run its tests, pass `-Zpolyfill --test`",
+            script.path().display()
+        );
+    }
+    if let Err(err) = crate::util::gc::auto(config, &ws.target_dir(), &script) {
+        config
+            .shell()
+            .warn(format!("failed to collect stale scripts: {err:#}"))?;
+    }
 
-    let mut build_config = cargo::core::compiler::BuildConfig::new(
+    let mut compile_opts = compile_options(
         config,
-        None,
-        false,
-        &[],
+        &script,
         cargo::core::compiler::CompileMode::Build,
+        compile_args,
     )?;
-    build_config.requested_profile =
-        cargo::util::interning::InternedString::new(if release { "release" } else { "dev" });
-    let compile_opts = cargo::ops::CompileOptions {
-        build_config,
-        cli_features: cargo::core::resolver::features::CliFeatures::from_command_line(
-            &[],
+    compile_opts.build_config.requested_profile = requested_profile(&ws, compile_args, "dev")?;
+    let is_host = compile_opts.build_config.single_requested_kind()?
+        == cargo::core::compiler::CompileKind::Host;
+
+    let compile = cargo::ops::compile(&ws, &compile_opts)?;
+    let (unit, path, script_meta) = match compile.binaries.as_slice() {
+        [cargo::core::compiler::UnitOutput {
+            unit,
+            path,
+            script_meta,
+        }] => (unit, path, script_meta),
+        binaries => anyhow::bail!(
+            "could not determine which binary to run for `{}`, {} were built",
+            script.path().display(),
+            binaries.len()
+        ),
+    };
+    let bin = script.link_bin(ws.target_dir().as_path_unlocked(), path)?;
+    if is_host {
+        // Record the absolute path as later runs may be from other directories
+        let mut process = compile.target_process(&bin, unit.kind, &unit.pkg, *script_meta)?;
+        script_env(
+            &mut process,
+            &script,
+            &compile_opts.build_config.requested_profile,
             false,
-            true,
-        )?,
-        spec: cargo::ops::Packages::Default,
-        filter: cargo::ops::CompileFilter::Default {
-            required_features_filterable: false,
-        },
-        target_rustdoc_args: None,
-        target_rustc_args: None,
-        target_rustc_crate_types: None,
-        rustdoc_document_private_items: false,
-        honor_rust_version: true,
+        );
+        crate::util::fingerprint::record(
+            &script,
+            ws.target_dir().as_path_unlocked(),
+            &compile_opts.build_config.requested_profile,
+            &bin,
+            &path.with_extension("d"),
+            &process,
+        )?;
+    }
+    let exe = match bin.strip_prefix(config.cwd()) {
+        Ok(path) if path.file_name() == Some(path.as_os_str()) => {
+            std::path::Path::new(".").join(path)
+        }
+        Ok(path) => path.to_path_buf(),
+        Err(_) => bin.clone(),
     };
+    let mut process = compile.target_process(&exe, unit.kind, &unit.pkg, *script_meta)?;
+    script_env(
+        &mut process,
+        &script,
+        &compile_opts.build_config.requested_profile,
+        from_stdin,
+    );
+    process.args(args).cwd(config.cwd());
+    config.shell().status("Running", process.to_string())?;
+
+    let arg0 = script.arg0(&exe, &process);
+    crate::util::exec_replace(&process, arg0.as_deref()).map_err(|err| {
+        let spawn_failed = err
+            .downcast_ref::<cargo_util::ProcessError>()
+            .map(|err| err.code.is_none())
+            == Some(true);
+        let target = match compile_args.target.as_deref() {
+            Some(target) if spawn_failed => target,
+            _ => return err,
+        };
+        let has_runner = config
+            .target_cfg_triple(target)
+            .map(|t| t.runner.is_some())
+            .unwrap_or(true);
+        if has_runner {
+            return err;
+        }
+        err.context(format!(
+            "could not run script built for `{target}`
 
-    cargo::ops::run(&ws, &compile_opts, args)
+help: to run it through an emulator, set `target.{target}.runner` in cargo's config"
+        ))
+    })
 }
 
-pub fn clean(config: &cargo::Config, manifest_path: &std::path::Path) -> CargoResult<()> {
-    let opts = cargo::ops::CleanOptions {
-        config,
-        spec: vec![],
-        targets: vec![],
-        requested_profile: cargo::util::interning::InternedString::new("dev"),
-        profile_specified: false,
-        doc: false,
+/// Tell the script where it came from and how it was built, as documented in the README
+fn script_env(
+    process: &mut cargo_util::ProcessBuilder,
+    script: &crate::util::script::RawScript,
+    profile: &str,
+    from_stdin: bool,
+) {
+    let path = script.path();
+    process.env("CARGO_EVAL_SCRIPT", path);
+    if let Some(dir) = path.parent() {
+        process.env("CARGO_EVAL_SCRIPT_DIR", dir);
+    }
+    process.env("CARGO_EVAL_SCRIPT_HASH", script.hash().to_string());
+    process.env("CARGO_EVAL_PROFILE", profile);
+    // Don't leak it into scripts run by a script from stdin
+    if from_stdin {
+        process.env("CARGO_EVAL_STDIN", "1");
+    } else {
+        process.env_remove("CARGO_EVAL_STDIN");
+    }
+}
+
+/// Print the manifest cargo will see for the script
+pub fn manifest(
+    config: &cargo::Config,
+    manifest_path: &std::path::Path,
+    format: ManifestFormat,
+) -> CargoResult<()> {
+    let script = crate::util::script::RawScript::parse_from(manifest_path)?;
+    let manifest = script.expanded_manifest(config)?;
+    let manifest = match format {
+        ManifestFormat::Toml => toml::to_string_pretty(&manifest)?,
+        ManifestFormat::Json => {
+            let mut manifest = serde_json::to_string_pretty(&manifest)?;
+            manifest.push('\n');
+            manifest
+        }
     };
+    config.shell().print_ansi_stdout(manifest.as_bytes())?;
+    Ok(())
+}
+
+/// Remove the script's build artifacts and generated workspace, leaving other scripts' alone
+pub fn clean(
+    config: &cargo::Config,
+    manifest_path: &std::path::Path,
+    compile_args: &CompileArgs,
+) -> CargoResult<()> {
     let script = crate::util::script::RawScript::parse_from(manifest_path)?;
     let ws = script.to_workspace(config)?;
-    cargo::ops::clean(&ws, &opts)?;
+
+    let requested_profiles = if compile_args.profile.is_some() {
+        vec![requested_profile(&ws, compile_args, "dev")?]
+    } else {
+        available_profiles(&ws)?.into_iter().collect()
+    };
+    let mut dir_names = std::collections::BTreeSet::new();
+    let mut profile_dirs = Vec::new();
+    for requested_profile in requested_profiles {
+        let profiles = cargo::core::profiles::Profiles::new(&ws, requested_profile)?;
+        // Skip profiles that were never built
+        let dir_name = profiles.get_dir_name();
+        let mut profile_dir = ws.target_dir().into_path_unlocked();
+        if let Some(target) = compile_args.target.as_deref() {
+            profile_dir.push(target);
+        }
+        profile_dir.push(dir_name.as_str());
+        if !profile_dir.exists() || !dir_names.insert(dir_name) {
+            continue;
+        }
+        profile_dirs.push(profile_dir);
+        if compile_args.target.is_none() {
+            crate::util::fingerprint::remove(
+                &script,
+                ws.target_dir().as_path_unlocked(),
+                &requested_profile,
+            )?;
+        }
+    }
+    // Rather than `cargo clean -p`, which removes the build output of every script with the same
+    // package name
+    crate::util::gc::remove_builds(config, &ws.target_dir(), &script, &profile_dirs)?;
+
+    script.remove_workspace(config, &ws.target_dir())?;
     Ok(())
 }
 
-pub fn test(config: &cargo::Config, manifest_path: &std::path::Path) -> CliResult {
+pub fn test(
+    config: &cargo::Config,
+    manifest_path: &std::path::Path,
+    compile_args: &CompileArgs,
+    harness_args: &HarnessArgs,
+) -> CliResult {
     let script = crate::util::script::RawScript::parse_from(manifest_path)?;
     let ws = script.to_workspace(config)?;
+    if harness_args.doc && !script.is_lib() {
+        return Err(anyhow::format_err!(
+            "`{}` is not a library, so has no doc-tests
 
-    let mut build_config = cargo::core::compiler::BuildConfig::new(
-        config,
-        None,
-        false,
-        &[],
-        cargo::core::compiler::CompileMode::Test,
-    )?;
-    build_config.requested_profile = cargo::util::interning::InternedString::new("test");
-    let compile_opts = cargo::ops::CompileOptions {
-        build_config,
-        cli_features: cargo::core::resolver::features::CliFeatures::from_command_line(
-            &[],
-            false,
-            true,
-        )?,
-        spec: cargo::ops::Packages::Default,
-        filter: cargo::ops::CompileFilter::from_raw_arguments(
-            false,
-            vec![],
-            true,
-            vec![],
-            false,
-            vec![],
-            false,
-            vec![],
-            false,
-            false,
-        ),
-        target_rustdoc_args: None,
-        target_rustc_args: None,
-        target_rustc_crate_types: None,
-        rustdoc_document_private_items: false,
-        honor_rust_version: true,
-    };
+help: remove `fn main` and top-level statements, or add an empty `[lib]` to its manifest",
+            script.path().display()
+        )
+        .into());
+    }
 
-    let ops = cargo::ops::TestOptions {
-        no_run: false,
-        no_fail_fast: false,
-        compile_opts,
+    let modes = if harness_args.doc {
+        vec![cargo::core::compiler::CompileMode::Doctest]
+    } else if script.is_lib() && script.rustc_args()?.is_some() {
+        // rustc arguments only apply to one unit, so doc-tests get their own run
+        vec![
+            cargo::core::compiler::CompileMode::Test,
+            cargo::core::compiler::CompileMode::Doctest,
+        ]
+    } else {
+        vec![cargo::core::compiler::CompileMode::Test]
     };
+    let args = harness_args
+        .args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+
+    let mut result = Ok(());
+    for mode in modes {
+        let mut compile_opts = compile_options(config, &script, mode, compile_args)?;
+        compile_opts.build_config.requested_profile = requested_profile(&ws, compile_args, "test")?;
 
-    cargo::ops::run_tests(&ws, &ops, &[])
+        let ops = cargo::ops::TestOptions {
+            no_run: harness_args.no_run,
+            no_fail_fast: harness_args.no_fail_fast,
+            compile_opts,
+        };
+        result = result.and(cargo::ops::run_tests(&ws, &ops, &args));
+        if result.is_err() && !harness_args.no_fail_fast {
+            break;
+        }
+    }
+    result
 }
 
-pub fn bench(config: &cargo::Config, manifest_path: &std::path::Path) -> CliResult {
+pub fn bench(
+    config: &cargo::Config,
+    manifest_path: &std::path::Path,
+    compile_args: &CompileArgs,
+    harness_args: &HarnessArgs,
+) -> CliResult {
     let script = crate::util::script::RawScript::parse_from(manifest_path)?;
     let ws = script.to_workspace(config)?;
 
-    let mut build_config = cargo::core::compiler::BuildConfig::new(
+    let mut compile_opts = compile_options(
         config,
-        None,
-        false,
-        &[],
+        &script,
         cargo::core::compiler::CompileMode::Bench,
+        compile_args,
     )?;
-    build_config.requested_profile = cargo::util::interning::InternedString::new("bench");
-    let compile_opts = cargo::ops::CompileOptions {
-        build_config,
-        cli_features: cargo::core::resolver::features::CliFeatures::from_command_line(
-            &[],
-            false,
-            true,
-        )?,
-        spec: cargo::ops::Packages::Default,
-        filter: cargo::ops::CompileFilter::from_raw_arguments(
+    compile_opts.build_config.requested_profile = requested_profile(&ws, compile_args, "bench")?;
+
+    let ops = cargo::ops::TestOptions {
+        no_run: harness_args.no_run,
+        no_fail_fast: harness_args.no_fail_fast,
+        compile_opts,
+    };
+    let args = harness_args
+        .args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+
+    cargo::ops::run_benches(&ws, &ops, &args)
+}
+
+/// The profile to build with, verifying the script or cargo's config defines it
+fn requested_profile(
+    ws: &cargo::core::Workspace<'_>,
+    compile_args: &CompileArgs,
+    default: &str,
+) -> CargoResult<cargo::util::interning::InternedString> {
+    let name = compile_args.profile.as_deref().unwrap_or(default);
+    let available = available_profiles(ws)?;
+    let name = cargo::util::interning::InternedString::new(name);
+    if !available.contains(&name) {
+        let available = available
+            .iter()
+            .map(|p| p.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        anyhow::bail!("profile `{name}` is not defined\n\navailable profiles: {available}");
+    }
+    Ok(name)
+}
+
+/// Built-in profiles and those defined by the script or cargo's config
+fn available_profiles(
+    ws: &cargo::core::Workspace<'_>,
+) -> CargoResult<std::collections::BTreeSet<cargo::util::interning::InternedString>> {
+    let mut available = ["dev", "release", "test", "bench", "doc"]
+        .into_iter()
+        .map(cargo::util::interning::InternedString::new)
+        .collect::<std::collections::BTreeSet<_>>();
+    if let Some(profiles) = ws.profiles() {
+        available.extend(profiles.get_all().keys().copied());
+    }
+    if let Some(profiles) = ws
+        .config()
+        .get::<Option<cargo::util::toml::TomlProfiles>>("profile")?
+    {
+        available.extend(profiles.get_all().keys().copied());
+    }
+    Ok(available)
+}
+
+fn compile_options(
+    config: &cargo::Config,
+    script: &crate::util::script::RawScript,
+    mode: cargo::core::compiler::CompileMode,
+    compile_args: &CompileArgs,
+) -> CargoResult<cargo::ops::CompileOptions> {
+    let targets = compile_args.target.iter().cloned().collect::<Vec<_>>();
+    let build_config =
+        cargo::core::compiler::BuildConfig::new(config, None, false, &targets, mode)?;
+
+    let features = compile_args
+        .features
+        .iter()
+        .cloned()
+        .chain(script.shebang_features())
+        .collect::<Vec<_>>();
+    let cli_features = cargo::core::resolver::features::CliFeatures::from_command_line(
+        &features,
+        compile_args.all_features,
+        !compile_args.no_default_features,
+    )?;
+
+    let filter = if mode == cargo::core::compiler::CompileMode::Build {
+        cargo::ops::CompileFilter::Default {
+            required_features_filterable: false,
+        }
+    } else if mode == cargo::core::compiler::CompileMode::Doctest {
+        cargo::ops::CompileFilter::lib_only()
+    } else if script.is_lib() && script.rustc_args()?.is_some() {
+        // Leaving doc-tests to their own run, see `test`
+        cargo::ops::CompileFilter::lib_only()
+    } else if script.is_lib() {
+        // Includes doc-tests when testing
+        cargo::ops::CompileFilter::Default {
+            required_features_filterable: true,
+        }
+    } else {
+        cargo::ops::CompileFilter::from_raw_arguments(
             false,
             vec![],
             true,
@@ -134,19 +411,61 @@ pub fn bench(config: &cargo::Config, manifest_path: &std::path::Path) -> CliResu
             vec![],
             false,
             false,
-        ),
+        )
+    };
+
+    Ok(cargo::ops::CompileOptions {
+        build_config,
+        cli_features,
+        spec: cargo::ops::Packages::Default,
+        filter,
         target_rustdoc_args: None,
-        target_rustc_args: None,
+        target_rustc_args: script.rustc_args()?,
         target_rustc_crate_types: None,
         rustdoc_document_private_items: false,
         honor_rust_version: true,
-    };
+    })
+}
 
-    let ops = cargo::ops::TestOptions {
-        no_run: false,
-        no_fail_fast: false,
-        compile_opts,
-    };
+/// The script's embedded manifest, editable as if it were a `Cargo.toml` next to the script
+fn local_manifest(
+    script: &crate::util::script::RawScript,
+) -> CargoResult<cargo::util::toml_mut::manifest::LocalManifest> {
+    let path = script
+        .path()
+        .parent()
+        .unwrap_or_else(|| std::path::Path::new(""))
+        .join("Cargo.toml");
+    let manifest = script
+        .manifest()
+        .parse::<cargo::util::toml_mut::manifest::Manifest>()
+        .map_err(|err| anyhow::format_err!("{err}"))
+        .with_context(|| format!("failed to parse manifest in {}", script.path().display()))?;
+    Ok(cargo::util::toml_mut::manifest::LocalManifest { path, manifest })
+}
+
+fn section_name(dep_table: &[String]) -> String {
+    if dep_table.len() >= 3 {
+        format!("{} for target `{}`", &dep_table[2], &dep_table[1])
+    } else {
+        dep_table[0].clone()
+    }
+}
 
-    cargo::ops::run_benches(&ws, &ops, &[])
+fn write_manifest(
+    script: &crate::util::script::RawScript,
+    manifest: &cargo::util::toml_mut::manifest::LocalManifest,
+    config: &cargo::Config,
+    dry_run: bool,
+    action: &str,
+) -> CargoResult<()> {
+    let body = script.replace_manifest(&manifest.manifest.to_string())?;
+    if dry_run {
+        config
+            .shell()
+            .warn(format!("aborting {action} due to dry run"))?;
+    } else {
+        cargo_util::paths::write(script.path(), body)?;
+    }
+    Ok(())
 }
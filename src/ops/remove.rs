@@ -0,0 +1,41 @@
+use cargo::util::toml_mut::manifest::DepTable;
+
+use crate::CargoResult;
+
+pub struct RemoveOptions<'a> {
+    pub config: &'a cargo::Config,
+    /// Dependency keys to remove
+    pub dependencies: Vec<String>,
+    pub section: DepTable,
+    pub dry_run: bool,
+}
+
+/// Remove dependencies from a script's embedded manifest
+pub fn remove(manifest_path: &std::path::Path, options: &RemoveOptions<'_>) -> CargoResult<()> {
+    let script = crate::util::script::RawScript::parse_from(manifest_path)?;
+    let mut manifest = super::local_manifest(&script)?;
+    let dep_table = options
+        .section
+        .to_table()
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    for dep in &options.dependencies {
+        options.config.shell().status(
+            "Removing",
+            format!("{dep} from {}", super::section_name(&dep_table)),
+        )?;
+        manifest.remove_from_table(&dep_table, dep)?;
+        // If that was the last reference to the crate, drop any features activating it
+        manifest.gc_dep(dep);
+    }
+
+    super::write_manifest(
+        &script,
+        &manifest,
+        options.config,
+        options.dry_run,
+        "remove",
+    )
+}
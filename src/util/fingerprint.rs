@@ -0,0 +1,334 @@
+//! Running a script's binary directly when nothing it was built from has changed
+//!
+//! After cargo builds and runs a script, the inputs to the build, including the files rustc read
+//! for it, are hashed and recorded next to the binary, along with the process cargo launched.  If the hash still matches, that process can
+//! be re-launched without loading the workspace or asking cargo about freshness.
+
+use crate::util::script::RawScript;
+use crate::CargoResult;
+
+/// Environment variables that can't affect the build
+const IGNORED_ENV: &[&str] = &[
+    "RUST_BACKTRACE",
+    "RUST_LIB_BACKTRACE",
+    "RUST_LOG",
+    "CARGO_LOG",
+];
+
+/// How cargo last launched the script
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Record {
+    fingerprint: String,
+    /// Files rustc read building the script, like its modules and `include!`s
+    inputs: Vec<String>,
+    /// The script's binary, which `program` may be a runner for
+    bin: String,
+    program: String,
+    args: Vec<String>,
+    env: std::collections::BTreeMap<String, Option<String>>,
+}
+
+/// Remember how cargo launched a freshly built script, minus the script's own arguments
+///
+/// `dep_info` lists the files the binary was built from, as written by cargo next to it
+pub fn record(
+    script: &RawScript,
+    target_dir: &std::path::Path,
+    profile: &str,
+    bin: &std::path::Path,
+    dep_info: &std::path::Path,
+    process: &cargo_util::ProcessBuilder,
+) -> CargoResult<()> {
+    let to_string = |s: &std::ffi::OsString| s.to_str().map(String::from);
+    let launch = (|| {
+        let inputs = crate::util::gc::read_dep_info_inputs(dep_info)?
+            .into_iter()
+            .map(|path| path.into_os_string().into_string().ok())
+            .collect::<Option<Vec<_>>>()?;
+        let bin = bin.to_str()?.to_owned();
+        let program = to_string(process.get_program())?;
+        let args = process.get_args().map(to_string).collect::<Option<_>>()?;
+        let env = process
+            .get_envs()
+            .iter()
+            .map(|(key, value)| match value {
+                Some(value) => Some((key.clone(), Some(to_string(value)?))),
+                None => Some((key.clone(), None)),
+            })
+            .collect::<Option<_>>()?;
+        Some((inputs, bin, program, args, env))
+    })();
+    let path = record_path(script, target_dir, profile)?;
+    let (inputs, bin, program, args, env) = match launch {
+        Some(launch) => launch,
+        // Not worth the complexity of encoding, so always go through cargo
+        None => {
+            let _ = std::fs::remove_file(path);
+            return Ok(());
+        }
+    };
+    let record = Record {
+        fingerprint: fingerprint(script, target_dir, profile, &inputs)?,
+        inputs,
+        bin,
+        program,
+        args,
+        env,
+    };
+    cargo_util::paths::write(&path, serde_json::to_string(&record)?)?;
+    Ok(())
+}
+
+/// The process to run the script, with its `argv[0]`, if its binary is fresh
+pub fn fresh_process(
+    script: &RawScript,
+    target_dir: &std::path::Path,
+    profile: &str,
+) -> Option<(cargo_util::ProcessBuilder, Option<std::ffi::OsString>)> {
+    let path = record_path(script, target_dir, profile).ok()?;
+    let record = std::fs::read_to_string(path).ok()?;
+    let record = serde_json::from_str::<Record>(&record).ok()?;
+    let bin = std::path::Path::new(&record.bin);
+    if !bin.exists() {
+        return None;
+    }
+    if record.fingerprint != fingerprint(script, target_dir, profile, &record.inputs).ok()? {
+        log::debug!("{} is stale", script.path().display());
+        return None;
+    }
+    log::debug!("{} is fresh", script.path().display());
+
+    let mut process = cargo_util::ProcessBuilder::new(record.program);
+    process.args(&record.args);
+    for (key, value) in record.env {
+        match value {
+            Some(value) => process.env(&key, value),
+            None => process.env_remove(&key),
+        };
+    }
+    let arg0 = script.arg0(bin, &process);
+    Some((process, arg0))
+}
+
+/// Forget how cargo launched the script, like when its binary is removed
+pub fn remove(script: &RawScript, target_dir: &std::path::Path, profile: &str) -> CargoResult<()> {
+    let path = record_path(script, target_dir, profile)?;
+    if path.exists() {
+        cargo_util::paths::remove_file(&path)?;
+    }
+    Ok(())
+}
+
+/// Recorded next to the binary, in the profile's directory
+fn record_path(
+    script: &RawScript,
+    target_dir: &std::path::Path,
+    profile: &str,
+) -> CargoResult<std::path::PathBuf> {
+    let dir_name = match profile {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        name => name,
+    };
+    Ok(target_dir
+        .join(dir_name)
+        .join(format!("{}.fingerprint", script.bin_name()?)))
+}
+
+/// Hash of everything the script's binary and its launch depend on
+fn fingerprint(
+    script: &RawScript,
+    target_dir: &std::path::Path,
+    profile: &str,
+    inputs: &[String],
+) -> CargoResult<String> {
+    let mut hasher = Hasher::default();
+    hasher.add("version", env!("CARGO_PKG_VERSION"));
+    hasher.add("script", script.hash().as_bytes());
+    hasher.add("profile", profile);
+
+    let workspace_root = script.workspace_root(target_dir)?;
+    let manifest = std::fs::read_to_string(workspace_root.join("Cargo.toml"))?;
+    hasher.add("manifest", &manifest);
+    let lockfile = std::fs::read(workspace_root.join("Cargo.lock")).unwrap_or_default();
+    hasher.add("lockfile", lockfile);
+
+    for input in inputs {
+        let path = std::path::Path::new(input);
+        hasher.add_mtime(path, mtime(path));
+    }
+
+    let manifest = manifest.parse::<toml::Table>()?;
+    for path in path_dependencies(&manifest) {
+        hasher.add_mtime(&path, newest_mtime(&path));
+    }
+    // Inherited settings are only brought in going through cargo
+    if let Some(root) = script.cargo_workspace()? {
+        for file in ["Cargo.toml", "Cargo.lock"] {
+            let path = root.join(file);
+            hasher.add_mtime(&path, mtime(&path));
+        }
+    }
+    // Their packages only point at the scripts, and are regenerated going through cargo
+    for dependency in script.script_dependencies(target_dir)? {
+        hasher.add(
+            &dependency.path().to_string_lossy(),
+            dependency.hash().as_bytes(),
+        );
+        let workspace_root = dependency.workspace_root(target_dir)?;
+        let manifest = std::fs::read_to_string(workspace_root.join("Cargo.toml"))?;
+        hasher.add("manifest", &manifest);
+        for path in path_dependencies(&manifest.parse::<toml::Table>()?) {
+            hasher.add_mtime(&path, newest_mtime(&path));
+        }
+    }
+
+    let mut env = std::env::vars_os()
+        .filter_map(|(key, value)| Some((key.into_string().ok()?, value)))
+        .filter(|(key, _)| {
+            (key.starts_with("CARGO") || key.starts_with("RUST"))
+                && !key.starts_with("CARGO_EVAL_")
+                && !IGNORED_ENV.contains(&key.as_str())
+                || key == cargo_util::paths::dylib_path_envvar()
+        })
+        .collect::<Vec<_>>();
+    env.sort();
+    for (key, value) in env {
+        hasher.add(&key, value.to_string_lossy().as_bytes());
+    }
+
+    let rustc = rustc_path();
+    hasher.add_mtime(&rustc, mtime(&rustc));
+    if let Some(rustup_home) = rustup_home() {
+        let settings = rustup_home.join("settings.toml");
+        hasher.add_mtime(&settings, mtime(&settings));
+        let toolchains = rustup_home.join("toolchains");
+        for toolchain in read_dir_sorted(&toolchains) {
+            let manifest = toolchain.join("lib/rustlib/multirust-channel-manifest.toml");
+            hasher.add_mtime(&toolchain, mtime(&manifest).or_else(|| mtime(&toolchain)));
+        }
+    }
+
+    let cwd = std::env::current_dir()?;
+    for dir in cwd.ancestors() {
+        for config in [
+            ".cargo/config",
+            ".cargo/config.toml",
+            "rust-toolchain",
+            "rust-toolchain.toml",
+        ] {
+            let path = dir.join(config);
+            hasher.add_mtime(&path, mtime(&path));
+        }
+    }
+    if let Ok(cargo_home) = home::cargo_home() {
+        for config in ["config", "config.toml"] {
+            let path = cargo_home.join(config);
+            hasher.add_mtime(&path, mtime(&path));
+        }
+    }
+
+    Ok(hasher.0.finalize().to_string())
+}
+
+#[derive(Default)]
+struct Hasher(blake3::Hasher);
+
+impl Hasher {
+    fn add(&mut self, key: &str, value: impl AsRef<[u8]>) {
+        let value = value.as_ref();
+        // Length-prefix fields so adjacent ones can't run together
+        for field in [key.as_bytes(), value] {
+            self.0.update(&(field.len() as u64).to_le_bytes());
+            self.0.update(field);
+        }
+    }
+
+    fn add_mtime(&mut self, path: &std::path::Path, mtime: Option<std::time::Duration>) {
+        let mtime = mtime.map(|d| d.as_nanos()).unwrap_or_default();
+        self.add(&path.to_string_lossy(), mtime.to_le_bytes());
+    }
+}
+
+/// Local packages the script depends on
+fn path_dependencies(manifest: &toml::Table) -> Vec<std::path::PathBuf> {
+    const DEP_TABLES: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+    let mut tables = DEP_TABLES
+        .iter()
+        .filter_map(|name| manifest.get(*name))
+        .collect::<Vec<_>>();
+    if let Some(targets) = manifest.get("target").and_then(|t| t.as_table()) {
+        for target in targets.values() {
+            tables.extend(DEP_TABLES.iter().filter_map(|name| target.get(*name)));
+        }
+    }
+    if let Some(patches) = manifest.get("patch").and_then(|t| t.as_table()) {
+        tables.extend(patches.values());
+    }
+    if let Some(workspace) = manifest.get("workspace") {
+        tables.extend(workspace.get("dependencies"));
+    }
+    tables
+        .into_iter()
+        .filter_map(|table| table.as_table())
+        .flat_map(|table| table.values())
+        .filter_map(|dep| dep.get("path")?.as_str())
+        .map(std::path::PathBuf::from)
+        .collect()
+}
+
+/// Most recent modification within a package, skipping build output and hidden files
+fn newest_mtime(path: &std::path::Path) -> Option<std::time::Duration> {
+    let mut newest = mtime(path)?;
+    if path.is_dir() {
+        for entry in read_dir_sorted(path) {
+            let name = entry.file_name().unwrap_or_default().to_string_lossy();
+            if name.starts_with('.') || name == "target" {
+                continue;
+            }
+            if let Some(mtime) = newest_mtime(&entry) {
+                newest = newest.max(mtime);
+            }
+        }
+    }
+    Some(newest)
+}
+
+fn mtime(path: &std::path::Path) -> Option<std::time::Duration> {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()
+}
+
+fn read_dir_sorted(path: &std::path::Path) -> Vec<std::path::PathBuf> {
+    let mut entries = std::fs::read_dir(path)
+        .map(|entries| entries.filter_map(|e| Some(e.ok()?.path())).collect())
+        .unwrap_or_else(|_| Vec::new());
+    entries.sort();
+    entries
+}
+
+/// The `rustc` cargo will find, without running it
+fn rustc_path() -> std::path::PathBuf {
+    if let Some(rustc) = std::env::var_os("RUSTC") {
+        return rustc.into();
+    }
+    let rustc = format!("rustc{}", std::env::consts::EXE_SUFFIX);
+    std::env::var_os("PATH")
+        .and_then(|paths| {
+            std::env::split_paths(&paths)
+                .map(|dir| dir.join(&rustc))
+                .find(|path| path.is_file())
+        })
+        .unwrap_or_else(|| rustc.into())
+}
+
+fn rustup_home() -> Option<std::path::PathBuf> {
+    std::env::var_os("RUSTUP_HOME")
+        .map(std::path::PathBuf::from)
+        .or_else(|| dirs_next::home_dir().map(|home| home.join(".rustup")))
+        .filter(|path| path.is_dir())
+}
@@ -0,0 +1,745 @@
+//! Tracking and collection of the workspaces and binaries generated for scripts
+
+use std::io::Read as _;
+use std::io::Seek as _;
+use std::io::Write as _;
+
+use anyhow::Context as _;
+
+use crate::CargoResult;
+
+const INDEX_PATH: &str = "eval/index.json";
+const AUTO_INTERVAL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+const DEFAULT_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Generated workspaces, keyed by their directory relative to the target directory
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Index {
+    /// When garbage was last collected automatically, in seconds since the Unix epoch
+    #[serde(default)]
+    pub last_gc: u64,
+    #[serde(default)]
+    pub scripts: std::collections::BTreeMap<String, Entry>,
+    /// Other target directories scripts were built into, like those of cargo workspaces they opted
+    /// into, tracked in the index of the shared one
+    #[serde(default)]
+    pub target_dirs: std::collections::BTreeSet<std::path::PathBuf>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Entry {
+    /// Script the workspace was generated for
+    pub source: std::path::PathBuf,
+    /// Hash of the script's content when last run
+    pub hash: String,
+    /// Name of the script's binary, prefixing its artifacts
+    pub bin_name: String,
+    /// In seconds since the Unix epoch
+    pub last_used: u64,
+    /// Bytes on disk, as of the last collection
+    #[serde(default)]
+    pub size: u64,
+}
+
+impl Entry {
+    /// The script's package, which cargo names some artifacts after, unlike its binary
+    fn package_name(&self) -> &str {
+        self.bin_name
+            .rsplit_once('_')
+            .map(|(name, _)| name)
+            .unwrap_or(&self.bin_name)
+    }
+}
+
+/// What to collect
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GcPolicy {
+    /// Remove scripts not run within this long
+    pub max_age: Option<std::time::Duration>,
+    /// Remove the least recently run scripts until the total size fits
+    pub max_size: Option<u64>,
+    /// Remove scripts that were deleted
+    pub missing_source: bool,
+}
+
+impl GcPolicy {
+    /// The policy from cargo's config, like `eval.gc.max-age`, falling back to collecting scripts
+    /// that are missing or unused for a month
+    pub fn from_config(config: &cargo::Config) -> CargoResult<Self> {
+        let gc = GcConfig::load(config)?;
+        let policy = Self {
+            max_age: gc.max_age.as_deref().map(parse_duration).transpose()?,
+            max_size: gc.max_size.as_deref().map(parse_size).transpose()?,
+            missing_source: gc.missing_source.unwrap_or_default(),
+        };
+        if policy == Self::default() {
+            Ok(Self {
+                max_age: Some(DEFAULT_MAX_AGE),
+                max_size: None,
+                missing_source: true,
+            })
+        } else {
+            Ok(policy)
+        }
+    }
+}
+
+/// `[eval.gc]` in cargo's config
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct GcConfig {
+    auto: Option<bool>,
+    max_age: Option<String>,
+    max_size: Option<String>,
+    missing_source: Option<bool>,
+}
+
+impl GcConfig {
+    fn load(config: &cargo::Config) -> CargoResult<Self> {
+        Ok(config.get::<Option<Self>>("eval.gc")?.unwrap_or_default())
+    }
+}
+
+/// Script removed by [`collect`]
+#[derive(Clone, Debug)]
+pub struct Collected {
+    pub workspace: String,
+    pub entry: Entry,
+}
+
+/// Note that a script's workspace was just used
+pub fn record(
+    config: &cargo::Config,
+    target_dir: &cargo::util::Filesystem,
+    workspace: String,
+    mut entry: Entry,
+) -> CargoResult<()> {
+    let (mut lock, mut index) = lock_index(config, target_dir)?;
+    if let Some(previous) = index.scripts.get(&workspace) {
+        entry.size = previous.size;
+    }
+    index.scripts.insert(workspace, entry);
+    save_index(&mut lock, &index)?;
+    drop(lock);
+
+    let shared = crate::config::target_dir(config)?;
+    if shared.as_path_unlocked() != target_dir.as_path_unlocked() {
+        let (mut lock, mut index) = lock_index(config, &shared)?;
+        if index
+            .target_dirs
+            .insert(target_dir.as_path_unlocked().to_owned())
+        {
+            save_index(&mut lock, &index)?;
+        }
+    }
+    Ok(())
+}
+
+/// Target directories with scripts: the shared one and any others scripts were built into
+pub fn target_dirs(config: &cargo::Config) -> CargoResult<Vec<cargo::util::Filesystem>> {
+    let shared = crate::config::target_dir(config)?;
+    let (mut lock, mut index) = lock_index(config, &shared)?;
+    let count = index.target_dirs.len();
+    index
+        .target_dirs
+        .retain(|target_dir| target_dir.join(INDEX_PATH).exists());
+    if index.target_dirs.len() != count {
+        save_index(&mut lock, &index)?;
+    }
+    let others = index
+        .target_dirs
+        .iter()
+        .map(|target_dir| cargo::util::Filesystem::new(target_dir.clone()));
+    Ok(std::iter::once(shared).chain(others).collect())
+}
+
+/// Remove a script's build output from `profile_dirs`, leaving that of other scripts alone
+pub fn remove_builds(
+    config: &cargo::Config,
+    target_dir: &cargo::util::Filesystem,
+    script: &crate::util::script::RawScript,
+    profile_dirs: &[std::path::PathBuf],
+) -> CargoResult<()> {
+    let (_lock, index) = lock_index(config, target_dir)?;
+    let target_dir = target_dir.as_path_unlocked();
+    let workspace = script.workspace_key(target_dir)?;
+    let entry = script.gc_entry()?;
+    let builds = Builds::scan(profile_dirs)?;
+    let shared_name = shares_package_name(&index.scripts, &workspace, &entry);
+    for path in builds.artifacts(&target_dir.join(&workspace), &entry, shared_name) {
+        remove(&path)?;
+    }
+    Ok(())
+}
+
+/// Stop tracking a script whose artifacts were removed
+pub fn forget(
+    config: &cargo::Config,
+    target_dir: &cargo::util::Filesystem,
+    workspace: &str,
+) -> CargoResult<()> {
+    let (mut lock, mut index) = lock_index(config, target_dir)?;
+    if index.scripts.remove(workspace).is_some() {
+        save_index(&mut lock, &index)?;
+    }
+    Ok(())
+}
+
+/// Remove the artifacts of scripts selected by `policy`, returning them with the remaining total
+pub fn collect(
+    config: &cargo::Config,
+    target_dir: &cargo::util::Filesystem,
+    policy: &GcPolicy,
+    dry_run: bool,
+) -> CargoResult<(Vec<Collected>, u64)> {
+    collect_(config, target_dir, policy, dry_run, None)
+}
+
+/// Collect garbage per `eval.gc` in cargo's config, if enabled and not done recently
+pub fn auto(
+    config: &cargo::Config,
+    target_dir: &cargo::util::Filesystem,
+    current: &crate::util::script::RawScript,
+) -> CargoResult<()> {
+    if GcConfig::load(config)?.auto != Some(true) {
+        return Ok(());
+    }
+    {
+        let (mut lock, mut index) = lock_index(config, target_dir)?;
+        let now = now();
+        if now.saturating_sub(index.last_gc) < AUTO_INTERVAL.as_secs() {
+            return Ok(());
+        }
+        index.last_gc = now;
+        save_index(&mut lock, &index)?;
+    }
+    let policy = GcPolicy::from_config(config)?;
+    let current = current.workspace_key(target_dir.as_path_unlocked())?;
+    let (collected, _) = collect_(config, target_dir, &policy, false, Some(&current))?;
+    if !collected.is_empty() {
+        let size = collected.iter().map(|c| c.entry.size).sum::<u64>();
+        let (size, unit) = cargo::util::human_readable_bytes(size);
+        config.shell().status(
+            "Collected",
+            format!("{} stale script(s), {size:.1}{unit}", collected.len()),
+        )?;
+    }
+    Ok(())
+}
+
+fn collect_(
+    config: &cargo::Config,
+    target_dir: &cargo::util::Filesystem,
+    policy: &GcPolicy,
+    dry_run: bool,
+    keep: Option<&str>,
+) -> CargoResult<(Vec<Collected>, u64)> {
+    let (mut lock, mut index) = lock_index(config, target_dir)?;
+    let target_dir = target_dir.as_path_unlocked();
+    let builds = Builds::scan(&profile_dirs(target_dir)?)?;
+    let scripts = index.scripts.clone();
+    let artifacts = |workspace: &str, entry: &Entry| {
+        let shared_name = shares_package_name(&scripts, workspace, entry);
+        artifacts(target_dir, &builds, workspace, entry, shared_name)
+    };
+    let now = now();
+
+    let mut garbage = Vec::new();
+    let mut live = Vec::new();
+    for (workspace, mut entry) in std::mem::take(&mut index.scripts) {
+        entry.size = artifacts(&workspace, &entry)
+            .iter()
+            .map(|path| disk_usage(path))
+            .sum();
+        let too_old = policy
+            .max_age
+            .map(|max_age| now.saturating_sub(entry.last_used) > max_age.as_secs())
+            .unwrap_or(false);
+        let missing = policy.missing_source && !entry.source.exists();
+        if Some(workspace.as_str()) != keep && (too_old || missing) {
+            garbage.push(Collected { workspace, entry });
+        } else {
+            live.push((workspace, entry));
+        }
+    }
+    if let Some(max_size) = policy.max_size {
+        live.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.last_used));
+        let mut total = 0;
+        for (workspace, entry) in std::mem::take(&mut live) {
+            total += entry.size;
+            if max_size < total && Some(workspace.as_str()) != keep {
+                total -= entry.size;
+                garbage.push(Collected { workspace, entry });
+            } else {
+                live.push((workspace, entry));
+            }
+        }
+    }
+    let remaining = live.iter().map(|(_, entry)| entry.size).sum();
+
+    if dry_run {
+        index.scripts.extend(
+            live.into_iter().chain(
+                garbage
+                    .iter()
+                    .map(|c| (c.workspace.clone(), c.entry.clone())),
+            ),
+        );
+    } else {
+        for collected in &garbage {
+            for path in artifacts(&collected.workspace, &collected.entry) {
+                remove(&path)?;
+            }
+            prune_empty_parents(target_dir, &target_dir.join(&collected.workspace));
+            // Only generated scripts are ours to clean up after, never the user's directories
+            if collected.entry.source.starts_with(target_dir.join("eval")) {
+                prune_empty_parents(target_dir, &collected.entry.source);
+            }
+        }
+        index.scripts.extend(live);
+    }
+    save_index(&mut lock, &index)?;
+
+    garbage.sort_by(|a, b| a.entry.source.cmp(&b.entry.source));
+    Ok((garbage, remaining))
+}
+
+/// Files and directories belonging to a script
+fn artifacts(
+    target_dir: &std::path::Path,
+    builds: &Builds,
+    workspace: &str,
+    entry: &Entry,
+    shared_name: bool,
+) -> Vec<std::path::PathBuf> {
+    let mut artifacts = Vec::new();
+    let workspace = target_dir.join(workspace);
+    // Scripts from stdin, `--expr`, and `--loop` are generated into the target directory
+    if entry.source.starts_with(target_dir.join("eval")) && entry.source.exists() {
+        artifacts.push(entry.source.clone());
+    }
+    artifacts.extend(builds.artifacts(&workspace, entry, shared_name));
+    if workspace.exists() {
+        artifacts.push(workspace);
+    }
+    artifacts
+}
+
+/// Whether another script has the same package name, and so the same name for its library crate
+fn shares_package_name(
+    scripts: &std::collections::BTreeMap<String, Entry>,
+    workspace: &str,
+    entry: &Entry,
+) -> bool {
+    scripts
+        .iter()
+        .any(|(other, e)| other != workspace && e.package_name() == entry.package_name())
+}
+
+/// What was built into a target directory
+///
+/// Binaries are named uniquely after their script, but cargo names fingerprints, and libraries
+/// and their incremental caches, after the package, which scripts with the same file name share.
+/// So these are attributed to scripts through the dep-info rustc writes for each crate, which
+/// names the crate root and the metadata hash that keeps cargo's files for the crate apart.
+struct Builds {
+    profiles: Vec<ProfileBuilds>,
+}
+
+struct ProfileBuilds {
+    /// Files in the profile directory itself and in `deps`
+    outputs: Vec<std::path::PathBuf>,
+    fingerprints: Vec<std::path::PathBuf>,
+    incremental: Vec<std::path::PathBuf>,
+    dep_infos: Vec<DepInfo>,
+}
+
+struct DepInfo {
+    path: std::path::PathBuf,
+    crate_root: std::path::PathBuf,
+    outputs: Vec<std::path::PathBuf>,
+    /// Metadata hash of the crate, for dep-info in `deps`
+    hash: Option<String>,
+}
+
+impl Builds {
+    fn scan(profile_dirs: &[std::path::PathBuf]) -> CargoResult<Self> {
+        let mut profiles = Vec::new();
+        for profile_dir in profile_dirs {
+            let mut outputs = read_dir(profile_dir)?;
+            outputs.retain(|path| path.is_file());
+            let deps = read_dir(&profile_dir.join("deps"))?;
+            let mut dep_infos = Vec::new();
+            for path in outputs.iter().chain(&deps) {
+                if path.extension().map(|e| e == "d") != Some(true) {
+                    continue;
+                }
+                if let Some((crate_root, outputs)) = read_dep_info(path) {
+                    let hash = path
+                        .starts_with(profile_dir.join("deps"))
+                        .then(|| {
+                            let stem = path.file_stem()?.to_str()?;
+                            Some(stem.rsplit_once('-')?.1.to_owned())
+                        })
+                        .flatten();
+                    dep_infos.push(DepInfo {
+                        path: path.clone(),
+                        crate_root,
+                        outputs,
+                        hash,
+                    });
+                }
+            }
+            outputs.extend(deps);
+            profiles.push(ProfileBuilds {
+                outputs,
+                fingerprints: read_dir(&profile_dir.join(".fingerprint"))?,
+                incremental: read_dir(&profile_dir.join("incremental"))?,
+                dep_infos,
+            });
+        }
+        Ok(Self { profiles })
+    }
+
+    /// Build output of the script with the generated `workspace`
+    fn artifacts(
+        &self,
+        workspace: &std::path::Path,
+        entry: &Entry,
+        shared_name: bool,
+    ) -> Vec<std::path::PathBuf> {
+        let copy = crate::util::script::copy_file_name(&entry.bin_name);
+        let crate_roots = [
+            entry.source.clone(),
+            workspace.join(&copy),
+            // Relative to the workspace root, when building the script itself
+            copy.into(),
+        ];
+        let bin_names = [entry.bin_name.clone(), entry.bin_name.replace('-', "_")];
+        let lib_name = entry.package_name().replace('-', "_");
+        let is_named = |path: &std::path::Path, name: &str| {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            file_name
+                .strip_prefix(name)
+                .map(|rest| rest.is_empty() || rest.starts_with(['.', '-']))
+                .unwrap_or(false)
+        };
+
+        let mut artifacts = Vec::new();
+        for profile in &self.profiles {
+            let mut hashes = Vec::new();
+            for dep_info in &profile.dep_infos {
+                if crate_roots.contains(&dep_info.crate_root) {
+                    artifacts.push(dep_info.path.clone());
+                    artifacts.extend(dep_info.outputs.iter().filter(|p| p.exists()).cloned());
+                    hashes.extend(dep_info.hash.as_deref());
+                }
+            }
+            let has_hash = |path: &std::path::Path| {
+                let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                hashes.iter().any(|hash| {
+                    file_name
+                        .split_once(&format!("-{hash}"))
+                        .map(|(_, rest)| rest.is_empty() || rest.starts_with('.'))
+                        .unwrap_or(false)
+                })
+            };
+            artifacts.extend(
+                profile
+                    .outputs
+                    .iter()
+                    .filter(|path| bin_names.iter().any(|name| is_named(path, name)))
+                    .cloned(),
+            );
+            artifacts.extend(profile.outputs.iter().filter(|p| has_hash(p)).cloned());
+            artifacts.extend(profile.fingerprints.iter().filter(|p| has_hash(p)).cloned());
+            artifacts.extend(
+                profile
+                    .incremental
+                    .iter()
+                    .filter(|path| {
+                        bin_names.iter().any(|name| is_named(path, name))
+                            || !shared_name && is_named(path, &lib_name)
+                    })
+                    .cloned(),
+            );
+        }
+        artifacts.sort();
+        artifacts.dedup();
+        artifacts
+    }
+}
+
+/// The crate root and outputs listed in a dep-info file
+fn read_dep_info(path: &std::path::Path) -> Option<(std::path::PathBuf, Vec<std::path::PathBuf>)> {
+    let dep_info = std::fs::read_to_string(path).ok()?;
+    let mut crate_root = None;
+    let mut outputs = Vec::new();
+    for line in dep_info.lines() {
+        // Skipping dependencies on their own, like `src/main.rs:`
+        let (targets, deps) = match line.split_once(": ") {
+            Some(rule) => rule,
+            None => continue,
+        };
+        if crate_root.is_none() {
+            crate_root = dep_info_paths(deps).into_iter().next();
+        }
+        outputs.extend(dep_info_paths(targets));
+    }
+    Some((crate_root?, outputs))
+}
+
+/// Every file listed as a dependency in a dep-info file
+pub(crate) fn read_dep_info_inputs(path: &std::path::Path) -> Option<Vec<std::path::PathBuf>> {
+    let dep_info = std::fs::read_to_string(path).ok()?;
+    let mut inputs = Vec::new();
+    for line in dep_info.lines() {
+        if let Some((_, deps)) = line.split_once(": ") {
+            inputs.extend(dep_info_paths(deps));
+        }
+    }
+    inputs.sort();
+    inputs.dedup();
+    Some(inputs)
+}
+
+/// Paths separated by spaces, with those in paths escaped, as rustc and cargo write dep-info
+fn dep_info_paths(raw: &str) -> Vec<std::path::PathBuf> {
+    let mut paths = Vec::new();
+    let mut path = String::new();
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&' ') => {
+                path.push(' ');
+                chars.next();
+            }
+            ' ' => {
+                if !path.is_empty() {
+                    paths.push(std::mem::take(&mut path).into());
+                }
+            }
+            _ => path.push(c),
+        }
+    }
+    if !path.is_empty() {
+        paths.push(path.into());
+    }
+    paths
+}
+
+fn read_dir(dir: &std::path::Path) -> CargoResult<Vec<std::path::PathBuf>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut paths = Vec::new();
+    for entry in entries {
+        paths.push(entry?.path());
+    }
+    Ok(paths)
+}
+
+fn remove(path: &std::path::Path) -> CargoResult<()> {
+    if path.is_dir() {
+        cargo_util::paths::remove_dir_all(path)
+    } else {
+        cargo_util::paths::remove_file(path)
+    }
+}
+
+/// Build directories for each profile, including when cross-compiling
+fn profile_dirs(target_dir: &std::path::Path) -> CargoResult<Vec<std::path::PathBuf>> {
+    let mut profile_dirs = Vec::new();
+    let entries = match std::fs::read_dir(target_dir) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(profile_dirs),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.join(".fingerprint").is_dir() {
+            profile_dirs.push(path);
+        } else if path.is_dir() {
+            for entry in std::fs::read_dir(&path)? {
+                let path = entry?.path();
+                if path.join(".fingerprint").is_dir() {
+                    profile_dirs.push(path);
+                }
+            }
+        }
+    }
+    Ok(profile_dirs)
+}
+
+fn disk_usage(path: &std::path::Path) -> u64 {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return 0,
+    };
+    if metadata.is_dir() {
+        std::fs::read_dir(path)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .map(|entry| disk_usage(&entry.path()))
+                    .sum()
+            })
+            .unwrap_or(0)
+    } else {
+        metadata.len()
+    }
+}
+
+/// Remove directories left empty between the workspace and `target/eval`
+pub(crate) fn prune_empty_parents(target_dir: &std::path::Path, workspace: &std::path::Path) {
+    let eval_dir = target_dir.join("eval");
+    for dir in workspace.ancestors().skip(1) {
+        if dir == eval_dir || std::fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
+
+fn lock_index(
+    config: &cargo::Config,
+    target_dir: &cargo::util::Filesystem,
+) -> CargoResult<(cargo::util::FileLock, Index)> {
+    let mut lock = target_dir.open_rw(INDEX_PATH, config, "script index")?;
+    let mut raw = String::new();
+    lock.read_to_string(&mut raw)?;
+    let index = if raw.trim().is_empty() {
+        Index::default()
+    } else {
+        match serde_json::from_str(&raw) {
+            Ok(index) => index,
+            Err(err) => {
+                config.shell().warn(format!(
+                    "discarding corrupt script index at {}: {err}",
+                    lock.path().display()
+                ))?;
+                Index::default()
+            }
+        }
+    };
+    Ok((lock, index))
+}
+
+fn save_index(lock: &mut cargo::util::FileLock, index: &Index) -> CargoResult<()> {
+    let raw = serde_json::to_string_pretty(index)?;
+    lock.file().set_len(0)?;
+    lock.rewind()?;
+    lock.write_all(raw.as_bytes())
+        .with_context(|| format!("failed to write {}", lock.path().display()))?;
+    Ok(())
+}
+
+pub(crate) fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Parse a duration like `30d`, `12h`, or `90s`
+pub fn parse_duration(raw: &str) -> CargoResult<std::time::Duration> {
+    let (value, unit) = split_unit(raw);
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => anyhow::bail!(
+            "invalid duration `{raw}`, expected a number followed by `s`, `m`, `h`, `d`, or `w`"
+        ),
+    };
+    let value = value
+        .parse::<u64>()
+        .with_context(|| format!("invalid duration `{raw}`"))?;
+    let secs = value
+        .checked_mul(scale)
+        .ok_or_else(|| anyhow::format_err!("duration `{raw}` is out of range"))?;
+    Ok(std::time::Duration::from_secs(secs))
+}
+
+/// Parse a size like `500M` or `2GiB`, defaulting to bytes
+pub fn parse_size(raw: &str) -> CargoResult<u64> {
+    let (value, unit) = split_unit(raw);
+    let scale: u64 = match unit.trim_end_matches("iB").trim_end_matches('B') {
+        "" => 1,
+        "K" | "k" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => anyhow::bail!(
+            "invalid size `{raw}`, expected a number optionally followed by `K`, `M`, `G`, or `T`"
+        ),
+    };
+    let value = value
+        .parse::<u64>()
+        .with_context(|| format!("invalid size `{raw}`"))?;
+    value
+        .checked_mul(scale)
+        .ok_or_else(|| anyhow::format_err!("size `{raw}` is out of range"))
+}
+
+fn split_unit(raw: &str) -> (&str, &str) {
+    let raw = raw.trim();
+    let unit_start = raw.find(|c: char| !c.is_ascii_digit()).unwrap_or(raw.len());
+    let (value, unit) = raw.split_at(unit_start);
+    (value, unit.trim())
+}
+
+#[cfg(test)]
+mod test_parse {
+    use super::*;
+
+    #[test]
+    fn test_duration() {
+        assert_eq!(
+            parse_duration("90s").unwrap(),
+            std::time::Duration::from_secs(90)
+        );
+        assert_eq!(
+            parse_duration("2w").unwrap(),
+            std::time::Duration::from_secs(14 * 24 * 60 * 60)
+        );
+        assert_eq!(
+            parse_duration("1 h").unwrap(),
+            std::time::Duration::from_secs(60 * 60)
+        );
+        snapbox::assert_eq(
+            "invalid duration `30`, expected a number followed by `s`, `m`, `h`, `d`, or `w`",
+            parse_duration("30").unwrap_err().to_string(),
+        );
+        snapbox::assert_eq(
+            "invalid duration `d`",
+            parse_duration("d").unwrap_err().to_string(),
+        );
+        snapbox::assert_eq(
+            "duration `99999999999999999w` is out of range",
+            parse_duration("99999999999999999w")
+                .unwrap_err()
+                .to_string(),
+        );
+    }
+
+    #[test]
+    fn test_size() {
+        assert_eq!(parse_size("100").unwrap(), 100);
+        assert_eq!(parse_size("2K").unwrap(), 2048);
+        assert_eq!(parse_size("500M").unwrap(), 500 << 20);
+        assert_eq!(parse_size("1GiB").unwrap(), 1 << 30);
+        assert_eq!(parse_size("1GB").unwrap(), 1 << 30);
+        snapbox::assert_eq(
+            "invalid size `1X`, expected a number optionally followed by `K`, `M`, `G`, or `T`",
+            parse_size("1X").unwrap_err().to_string(),
+        );
+        snapbox::assert_eq(
+            "size `99999999999T` is out of range",
+            parse_size("99999999999T").unwrap_err().to_string(),
+        );
+    }
+}
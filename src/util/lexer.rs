@@ -0,0 +1,443 @@
+//! A minimal Rust lexer, enough to find our way around the top of a script
+//!
+//! This only distinguishes what is needed for locating embedded manifests and top-level items:
+//! comments, whitespace, identifiers, literals, and single-character punctuation.  It never fails;
+//! malformed input is reported through [`TokenKind`] (e.g. an unterminated block comment).
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum TokenKind {
+    /// `#!/usr/bin/env cargo-eval`, only at the start of the file
+    Shebang,
+    Whitespace,
+    LineComment {
+        doc: Option<DocStyle>,
+    },
+    BlockComment {
+        doc: Option<DocStyle>,
+        terminated: bool,
+    },
+    Ident,
+    Lifetime,
+    Literal {
+        kind: LiteralKind,
+        terminated: bool,
+    },
+    /// Any other single character
+    Punct(char),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum DocStyle {
+    /// `//!` or `/*!`
+    Inner,
+    /// `///` or `/**`
+    Outer,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum LiteralKind {
+    Char,
+    Str,
+    /// `r#"..."#` with the number of `#`s
+    RawStr {
+        hashes: usize,
+    },
+    Number,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Token {
+    pub(crate) kind: TokenKind,
+    pub(crate) span: std::ops::Range<usize>,
+}
+
+impl Token {
+    pub(crate) fn text<'s>(&self, input: &'s str) -> &'s str {
+        &input[self.span.clone()]
+    }
+
+    /// Whitespace and non-doc comments
+    pub(crate) fn is_trivia(&self) -> bool {
+        matches!(
+            self.kind,
+            TokenKind::Whitespace
+                | TokenKind::LineComment { doc: None }
+                | TokenKind::BlockComment { doc: None, .. }
+        )
+    }
+}
+
+pub(crate) fn tokenize(input: &str) -> Lexer<'_> {
+    Lexer { input, pos: 0 }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Lexer<'s> {
+    input: &'s str,
+    pos: usize,
+}
+
+impl<'s> Lexer<'s> {
+    fn rest(&self) -> &'s str {
+        &self.input[self.pos..]
+    }
+
+    fn shebang_len(&self) -> Option<usize> {
+        if self.pos != 0 {
+            return None;
+        }
+        let rest = self.rest().strip_prefix("#!")?;
+        // `#![attr]` is an inner attribute, not a shebang, even with trivia between `!` and `[`
+        let next = tokenize(rest).find(|t| !t.is_trivia());
+        if next.map(|t| t.kind == TokenKind::Punct('[')) == Some(true) {
+            return None;
+        }
+        let line_len = self.rest().find('\n').unwrap_or(self.rest().len());
+        Some(line_len)
+    }
+
+    fn block_comment_len(rest: &str) -> (usize, bool) {
+        let mut depth = 0_usize;
+        let mut pos = 0;
+        while pos < rest.len() {
+            let tail = &rest[pos..];
+            if tail.starts_with("/*") {
+                depth += 1;
+                pos += 2;
+            } else if tail.starts_with("*/") {
+                depth -= 1;
+                pos += 2;
+                if depth == 0 {
+                    return (pos, true);
+                }
+            } else {
+                pos += tail.chars().next().map(char::len_utf8).unwrap_or(1);
+            }
+        }
+        (rest.len(), false)
+    }
+
+    fn quoted_len(rest: &str, quote: char) -> (usize, bool) {
+        let mut chars = rest.char_indices().skip(1);
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    chars.next();
+                }
+                c if c == quote => return (i + c.len_utf8(), true),
+                _ => {}
+            }
+        }
+        (rest.len(), false)
+    }
+
+    /// `rest` starts just after the `r`
+    fn raw_str_len(rest: &str) -> Option<(usize, usize, bool)> {
+        let hashes = rest.len() - rest.trim_start_matches('#').len();
+        let body = rest[hashes..].strip_prefix('"')?;
+        let close = format!("\"{}", "#".repeat(hashes));
+        let len = match body.find(&close) {
+            Some(end) => (hashes + 1 + end + close.len(), hashes, true),
+            None => (rest.len(), hashes, false),
+        };
+        Some(len)
+    }
+
+    fn ident_len(rest: &str) -> usize {
+        rest.char_indices()
+            .find(|(_, c)| !is_ident_continue(*c))
+            .map(|(i, _)| i)
+            .unwrap_or(rest.len())
+    }
+}
+
+impl<'s> Iterator for Lexer<'s> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let rest = self.rest();
+        let first = rest.chars().next()?;
+        let (kind, len) = if let Some(len) = self.shebang_len() {
+            (TokenKind::Shebang, len)
+        } else if first.is_whitespace() {
+            let len = rest
+                .char_indices()
+                .find(|(_, c)| !c.is_whitespace())
+                .map(|(i, _)| i)
+                .unwrap_or(rest.len());
+            (TokenKind::Whitespace, len)
+        } else if rest.starts_with("//") {
+            let doc = if rest.starts_with("//!") {
+                Some(DocStyle::Inner)
+            } else if rest.starts_with("///") && !rest.starts_with("////") {
+                Some(DocStyle::Outer)
+            } else {
+                None
+            };
+            let len = rest.find('\n').unwrap_or(rest.len());
+            (TokenKind::LineComment { doc }, len)
+        } else if rest.starts_with("/*") {
+            let doc = if rest.starts_with("/*!") {
+                Some(DocStyle::Inner)
+            } else if rest.starts_with("/**")
+                && !rest.starts_with("/***")
+                && !rest.starts_with("/**/")
+            {
+                Some(DocStyle::Outer)
+            } else {
+                None
+            };
+            let (len, terminated) = Self::block_comment_len(rest);
+            (TokenKind::BlockComment { doc, terminated }, len)
+        } else if let Some((len, hashes, terminated)) = rest
+            .strip_prefix('r')
+            .or_else(|| rest.strip_prefix("br"))
+            .and_then(Self::raw_str_len)
+        {
+            let prefix = if rest.starts_with('b') { 2 } else { 1 };
+            let kind = LiteralKind::RawStr { hashes };
+            (TokenKind::Literal { kind, terminated }, prefix + len)
+        } else if rest.starts_with("b\"") || rest.starts_with("b'") {
+            let quote = rest[1..].chars().next().unwrap();
+            let (len, terminated) = Self::quoted_len(&rest[1..], quote);
+            let kind = if quote == '"' {
+                LiteralKind::Str
+            } else {
+                LiteralKind::Char
+            };
+            (TokenKind::Literal { kind, terminated }, 1 + len)
+        } else if first == '"' {
+            let (len, terminated) = Self::quoted_len(rest, '"');
+            let kind = LiteralKind::Str;
+            (TokenKind::Literal { kind, terminated }, len)
+        } else if first == '\'' {
+            let mut chars = rest.chars().skip(1);
+            let is_char = matches!(
+                (chars.next(), chars.next()),
+                (Some('\\'), _) | (Some(_), Some('\''))
+            );
+            if is_char {
+                let (len, terminated) = Self::quoted_len(rest, '\'');
+                let kind = LiteralKind::Char;
+                (TokenKind::Literal { kind, terminated }, len)
+            } else {
+                (TokenKind::Lifetime, 1 + Self::ident_len(&rest[1..]))
+            }
+        } else if first.is_ascii_digit() {
+            let kind = LiteralKind::Number;
+            let len = Self::ident_len(rest);
+            (
+                TokenKind::Literal {
+                    kind,
+                    terminated: true,
+                },
+                len,
+            )
+        } else if is_ident_start(first) {
+            let len = if let Some(raw) = rest.strip_prefix("r#") {
+                2 + Self::ident_len(raw)
+            } else {
+                Self::ident_len(rest)
+            };
+            (TokenKind::Ident, len)
+        } else {
+            (TokenKind::Punct(first), first.len_utf8())
+        };
+
+        let span = self.pos..self.pos + len;
+        self.pos += len;
+        Some(Token { kind, span })
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c == '_' || c.is_alphabetic()
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c == '_' || c.is_alphanumeric()
+}
+
+/// Skips over a bracketed group, returning the tokens inside it and the closing token
+///
+/// `tokens` must be positioned just after the opening `open`.
+pub(crate) fn take_group(
+    tokens: &mut impl Iterator<Item = Token>,
+    open: char,
+    close: char,
+) -> Option<(Vec<Token>, Token)> {
+    let mut depth = 1_usize;
+    let mut group = Vec::new();
+    for token in tokens {
+        match token.kind {
+            TokenKind::Punct(c) if c == open => depth += 1,
+            TokenKind::Punct(c) if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((group, token));
+                }
+            }
+            _ => {}
+        }
+        group.push(token);
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kinds(input: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(input)
+            .map(|t| (t.kind, t.text(input)))
+            .collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn test_shebang() {
+        assert_eq!(
+            kinds("#!/usr/bin/env cargo-eval\nfn"),
+            vec![
+                (TokenKind::Shebang, "#!/usr/bin/env cargo-eval"),
+                (TokenKind::Whitespace, "\n"),
+                (TokenKind::Ident, "fn"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inner_attribute_is_not_shebang() {
+        assert_eq!(
+            kinds("#! /* */ [a]"),
+            vec![
+                (TokenKind::Punct('#'), "#"),
+                (TokenKind::Punct('!'), "!"),
+                (TokenKind::Whitespace, " "),
+                (
+                    TokenKind::BlockComment {
+                        doc: None,
+                        terminated: true
+                    },
+                    "/* */"
+                ),
+                (TokenKind::Whitespace, " "),
+                (TokenKind::Punct('['), "["),
+                (TokenKind::Ident, "a"),
+                (TokenKind::Punct(']'), "]"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(
+            kinds("//! a\r\n/// b\n//// c\n/*! /* nested */ */ /** d */ /**/"),
+            vec![
+                (
+                    TokenKind::LineComment {
+                        doc: Some(DocStyle::Inner)
+                    },
+                    "//! a\r"
+                ),
+                (TokenKind::Whitespace, "\n"),
+                (
+                    TokenKind::LineComment {
+                        doc: Some(DocStyle::Outer)
+                    },
+                    "/// b"
+                ),
+                (TokenKind::Whitespace, "\n"),
+                (TokenKind::LineComment { doc: None }, "//// c"),
+                (TokenKind::Whitespace, "\n"),
+                (
+                    TokenKind::BlockComment {
+                        doc: Some(DocStyle::Inner),
+                        terminated: true
+                    },
+                    "/*! /* nested */ */"
+                ),
+                (TokenKind::Whitespace, " "),
+                (
+                    TokenKind::BlockComment {
+                        doc: Some(DocStyle::Outer),
+                        terminated: true
+                    },
+                    "/** d */"
+                ),
+                (TokenKind::Whitespace, " "),
+                (
+                    TokenKind::BlockComment {
+                        doc: None,
+                        terminated: true
+                    },
+                    "/**/"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        assert_eq!(
+            kinds("/*! /* */"),
+            vec![(
+                TokenKind::BlockComment {
+                    doc: Some(DocStyle::Inner),
+                    terminated: false
+                },
+                "/*! /* */"
+            ),]
+        );
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(
+            kinds(r##"'a' 'b "c\"" r#"d"# br"e" 'f'"##),
+            vec![
+                (
+                    TokenKind::Literal {
+                        kind: LiteralKind::Char,
+                        terminated: true
+                    },
+                    "'a'"
+                ),
+                (TokenKind::Whitespace, " "),
+                (TokenKind::Lifetime, "'b"),
+                (TokenKind::Whitespace, " "),
+                (
+                    TokenKind::Literal {
+                        kind: LiteralKind::Str,
+                        terminated: true
+                    },
+                    r#""c\"""#
+                ),
+                (TokenKind::Whitespace, " "),
+                (
+                    TokenKind::Literal {
+                        kind: LiteralKind::RawStr { hashes: 1 },
+                        terminated: true
+                    },
+                    r##"r#"d"#"##
+                ),
+                (TokenKind::Whitespace, " "),
+                (
+                    TokenKind::Literal {
+                        kind: LiteralKind::RawStr { hashes: 0 },
+                        terminated: true
+                    },
+                    r#"br"e""#
+                ),
+                (TokenKind::Whitespace, " "),
+                (
+                    TokenKind::Literal {
+                        kind: LiteralKind::Char,
+                        terminated: true
+                    },
+                    "'f'"
+                ),
+            ]
+        );
+    }
+}
@@ -2,6 +2,9 @@ use anyhow::Context as _;
 
 use crate::CargoResult;
 
+pub mod fingerprint;
+pub mod gc;
+mod lexer;
 pub mod script;
 
 pub fn write_if_changed(path: &std::path::Path, new: &str) -> CargoResult<()> {
@@ -14,3 +17,31 @@ pub fn write_if_changed(path: &std::path::Path, new: &str) -> CargoResult<()> {
     }
     Ok(())
 }
+
+/// Replace the current process with `process`, leaving signals, job control, the terminal and the
+/// exit status to the script as if it had been run directly
+///
+/// `arg0` overrides `argv[0]` where supported.  Where replacing the process isn't possible,
+/// `process` is run as a child instead.
+pub fn exec_replace(
+    process: &cargo_util::ProcessBuilder,
+    arg0: Option<&std::ffi::OsStr>,
+) -> CargoResult<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt as _;
+
+        let mut command = process.build_command();
+        if let Some(arg0) = arg0 {
+            command.arg0(arg0);
+        }
+        let err = command.exec();
+        log::debug!("failed to exec `{process}`, running it as a child: {err}");
+        process.exec()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = arg0;
+        process.exec_replace()
+    }
+}
@@ -1,5 +1,11 @@
 use anyhow::Context as _;
 
+use super::lexer::take_group;
+use super::lexer::tokenize;
+use super::lexer::DocStyle;
+use super::lexer::LiteralKind;
+use super::lexer::Token;
+use super::lexer::TokenKind;
 use crate::CargoResult;
 
 const DEFAULT_EDITION: &str = "2021";
@@ -8,8 +14,16 @@ const DEFAULT_PUBLISH: bool = false;
 
 pub struct RawScript {
     manifest: String,
+    /// How `manifest` was embedded, if present
+    manifest_kind: Option<&'static str>,
+    /// Locations of `manifest` within `body`
+    manifest_map: SourceMap,
     body: String,
+    /// Source to hand to rustc when `body` is not valid Rust on its own
+    source: Option<String>,
     path: std::path::PathBuf,
+    /// Build a library rather than a binary
+    lib: bool,
 }
 
 impl RawScript {
@@ -20,38 +34,196 @@ impl RawScript {
     }
 
     pub fn parse(body: &str, path: &std::path::Path) -> CargoResult<Self> {
-        let comment = match extract_comment(body) {
-            Ok(manifest) => Some(manifest),
-            Err(err) => {
-                log::trace!("failed to extract doc comment: {err}");
-                None
+        let mut manifests = Vec::new();
+        let mut source = None;
+        if let Some((manifest, stripped)) = extract_frontmatter(body)? {
+            manifests.push(("frontmatter", manifest));
+            source = Some(stripped);
+        }
+        if let Some((manifest, stripped)) = extract_attribute(source.as_deref().unwrap_or(body))? {
+            manifests.push(("attribute", manifest));
+            source = Some(stripped);
+        }
+        let comment = extract_comment(source.as_deref().unwrap_or(body))?;
+        if let Some((comment, comment_map)) = comment {
+            if let Some((manifest, manifest_map)) = extract_manifest(&comment)? {
+                let map = manifest_map.compose(&comment_map);
+                manifests.push(("doc-comment", (manifest, map)));
             }
+        } else {
+            log::trace!("no doc-comment found");
         }
-        .unwrap_or_default();
-        let manifest = match extract_manifest(&comment)? {
-            Some(manifest) => Some(manifest),
+        if 1 < manifests.len() {
+            let kinds = manifests
+                .iter()
+                .map(|(kind, _)| *kind)
+                .collect::<Vec<_>>()
+                .join(", ");
+            anyhow::bail!("multiple `cargo` manifests present ({kinds}); only one is allowed")
+        }
+        let (manifest_kind, (manifest, manifest_map)) = match manifests.pop() {
+            Some((kind, manifest)) => (Some(kind), manifest),
             None => {
                 log::trace!("failed to extract manifest");
-                None
+                (None, Default::default())
+            }
+        };
+        // Invalid manifests are reported when expanding them
+        let explicit_lib = toml::from_str::<toml::Table>(&manifest)
+            .map(|manifest| manifest.contains_key("lib"))
+            .unwrap_or(false);
+        let lib = explicit_lib || is_library(source.as_deref().unwrap_or(body));
+        if !lib {
+            if let Some(wrapped) = implicit_main(source.as_deref().unwrap_or(body))? {
+                source = Some(wrapped);
             }
         }
-        .unwrap_or_default();
+        // The sanitized copy is compiled from the script's workspace, away from its files
+        if let Some(stripped) = source.as_deref().filter(|s| uses_relative_paths(s)) {
+            let dir = path
+                .parent()
+                .ok_or_else(|| anyhow::format_err!("no parent directory for {}", path.display()))?;
+            source = Some(absolute_paths(stripped, dir)?);
+        }
         let body = body.to_owned();
         let path = path.to_owned();
         Ok(Self {
             manifest,
+            manifest_kind,
+            manifest_map,
             body,
+            source,
             path,
+            lib,
         })
     }
 
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// Whether the script is a library, from an empty `[lib]` or having only items without a
+    /// `fn main`
+    pub fn is_lib(&self) -> bool {
+        self.lib
+    }
+
+    /// The embedded manifest, as written in the script
+    pub fn manifest(&self) -> &str {
+        &self.manifest
+    }
+
+    /// Creates the script's new content with `manifest` embedded in place of the current one
+    ///
+    /// The way the manifest is embedded (frontmatter, attribute, or doc-comment with its comment
+    /// markers and indentation) is preserved, as is the rest of the script.  When there is no
+    /// manifest, a doc-comment one is added after the shebang.
+    pub fn replace_manifest(&self, manifest: &str) -> CargoResult<String> {
+        let newline = if self.body.contains("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        };
+
+        if self.manifest_kind.is_none() {
+            let start = match tokenize(&self.body).next() {
+                Some(Token {
+                    kind: TokenKind::Shebang,
+                    span,
+                }) => (span.end + 1).min(self.body.len()),
+                _ => 0,
+            };
+            let mut body = self.body[..start].to_owned();
+            if !body.is_empty() && !body.ends_with('\n') {
+                body.push_str(newline);
+            }
+            body.push_str("//! ```cargo");
+            body.push_str(newline);
+            for line in manifest.lines() {
+                body.push_str(if line.is_empty() { "//!" } else { "//! " });
+                body.push_str(line);
+                body.push_str(newline);
+            }
+            body.push_str("//! ```");
+            body.push_str(newline);
+            body.push_str(&self.body[start..]);
+            return Ok(body);
+        }
+
+        if self.manifest.is_empty() && self.manifest_map == SourceMap::default() {
+            anyhow::bail!(
+                "cannot locate the empty `cargo` manifest in {}; add a table to it first",
+                self.path.display()
+            );
+        }
+        let prefix = self.manifest_prefix();
+        let mut start = self.manifest_map.to_script(0);
+        // Take over the prefix so every line is written the same way
+        let line_start = self.body[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let prefix_first = !prefix.is_empty() && self.body[line_start..start] == *prefix;
+        if prefix_first {
+            start = line_start;
+        }
+        let mut end = match self.manifest.len().checked_sub(1) {
+            Some(last) => self.manifest_map.to_script(last) + 1,
+            None => start,
+        };
+        if self.body[..end].ends_with('\r') && self.body[end..].starts_with('\n') {
+            end += 1;
+        }
+
+        let mut body = self.body[..start].to_owned();
+        for (i, line) in manifest.lines().enumerate() {
+            if i != 0 {
+                body.push_str(newline);
+            }
+            if i != 0 || prefix_first {
+                body.push_str(if line.is_empty() {
+                    prefix.trim_end()
+                } else {
+                    prefix
+                });
+            }
+            body.push_str(line);
+        }
+        if manifest.ends_with('\n') {
+            body.push_str(newline);
+        }
+        body.push_str(&self.body[end..]);
+        Ok(body)
+    }
+
+    /// What precedes each manifest line in the script, like `//! `
+    fn manifest_prefix(&self) -> &str {
+        // Skip the first line as it may share a line with the opening delimiter (e.g. `r#"`) and
+        // blank lines as they may lack trailing whitespace
+        let mut line_start = 0;
+        let mut offset = None;
+        for (i, line) in self.manifest.split_inclusive('\n').enumerate() {
+            if i != 0 && !line.trim().is_empty() {
+                offset = Some(line_start);
+                break;
+            }
+            line_start += line.len();
+        }
+        let offset = self.manifest_map.to_script(offset.unwrap_or(0));
+        let line_start = self.body[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let prefix = &self.body[line_start..offset];
+        if prefix
+            .chars()
+            .all(|c| c.is_whitespace() || matches!(c, '/' | '!' | '*'))
+        {
+            prefix
+        } else {
+            ""
+        }
+    }
+
     pub fn to_workspace<'cfg>(
         &self,
         config: &'cfg cargo::Config,
     ) -> CargoResult<cargo::core::Workspace<'cfg>> {
-        let target_dir = config.target_dir().transpose().unwrap_or_else(|| {
-            crate::config::default_target_dir().map(cargo::util::Filesystem::new)
-        })?;
+        let target_dir = self.target_dir(config)?;
         // HACK: without cargo knowing about embedded manifests, the only way to create a
         // `Workspace` is either
         // - Create a temporary one on disk
@@ -59,77 +231,410 @@ impl RawScript {
         //   from the registry rather than what we already have on memory, causing it to fail
         //   because the registry doesn't know about embedded manifests.
         let manifest_path = self.write(config, target_dir.as_path_unlocked())?;
-        let workspace = cargo::core::Workspace::new(&manifest_path, config)?;
+        self.record_use(config, &target_dir)?;
+        for dependency in self.script_dependencies(target_dir.as_path_unlocked())? {
+            dependency.write(config, target_dir.as_path_unlocked())?;
+            dependency.record_use(config, &target_dir)?;
+        }
+        let mut workspace = cargo::core::Workspace::new(&manifest_path, config)?;
+        // Otherwise, cargo derives the target directory from the temporary workspace's location
+        workspace.set_target_dir(target_dir);
         Ok(workspace)
     }
 
+    /// Mark the script, and the scripts it depends on, as used when running its binary without
+    /// loading its workspace
+    pub fn record_fresh_use(
+        &self,
+        config: &cargo::Config,
+        target_dir: &cargo::util::Filesystem,
+    ) -> CargoResult<()> {
+        self.record_use(config, target_dir)?;
+        for dependency in self.script_dependencies(target_dir.as_path_unlocked())? {
+            dependency.record_use(config, target_dir)?;
+        }
+        Ok(())
+    }
+
+    fn record_use(
+        &self,
+        config: &cargo::Config,
+        target_dir: &cargo::util::Filesystem,
+    ) -> CargoResult<()> {
+        let workspace = self.workspace_key(target_dir.as_path_unlocked())?;
+        crate::util::gc::record(config, target_dir, workspace, self.gc_entry()?)
+    }
+
+    /// How the script is tracked in the [`crate::util::gc::Index`], as of now
+    pub(crate) fn gc_entry(&self) -> CargoResult<crate::util::gc::Entry> {
+        Ok(crate::util::gc::Entry {
+            source: self.path.clone(),
+            hash: self.hash().to_string(),
+            bin_name: self.bin_name()?,
+            last_used: crate::util::gc::now(),
+            size: 0,
+        })
+    }
+
     fn write(
         &self,
         config: &cargo::Config,
         target_dir: &std::path::Path,
     ) -> CargoResult<std::path::PathBuf> {
-        let hash = self.hash().to_string();
-        assert_eq!(hash.len(), 64);
-        let mut workspace_root = target_dir.to_owned();
-        workspace_root.push("eval");
-        workspace_root.push(&hash[0..2]);
-        workspace_root.push(&hash[2..4]);
-        workspace_root.push(&hash[4..]);
-        workspace_root.push(self.package_name()?);
+        let workspace_root = self.workspace_root(target_dir)?;
         std::fs::create_dir_all(&workspace_root).with_context(|| {
             format!(
                 "failed to create temporary workspace at {}",
                 workspace_root.display()
             )
         })?;
+        let bin_path = self.bin_path(&workspace_root)?;
+        if let Some(source) = &self.source {
+            crate::util::write_if_changed(&bin_path, source)?;
+        }
         let manifest_path = workspace_root.join("Cargo.toml");
-        let manifest = self
-            .expand_manifest_(config)
-            .with_context(|| format!("failed to parse manifest at {}", self.path.display()))?;
-        let manifest = remap_paths(
-            manifest,
-            self.path.parent().ok_or_else(|| {
-                anyhow::format_err!("no parent directory for {}", self.path.display())
-            })?,
-        )?;
+        let manifest = self.resolved_manifest(&bin_path, target_dir, config)?;
         let manifest = toml::to_string_pretty(&manifest)?;
         crate::util::write_if_changed(&manifest_path, &manifest)?;
+        let workspace = self.cargo_workspace()?;
+        // `--locked` and `--frozen` hold the script to the versions it was resolved with
+        if let Some(root) = workspace.filter(|_| config.lock_update_allowed()) {
+            // Start from the workspace's versions, keeping what was resolved for the script since
+            let lockfile = root.join("Cargo.lock");
+            let script_lockfile = workspace_root.join("Cargo.lock");
+            let modified = |path: &std::path::Path| std::fs::metadata(path)?.modified();
+            let outdated = match (modified(&lockfile), modified(&script_lockfile)) {
+                (Ok(modified), Ok(script_modified)) => script_modified < modified,
+                (Ok(_), Err(_)) => true,
+                (Err(_), _) => false,
+            };
+            if outdated {
+                cargo_util::paths::copy(&lockfile, &script_lockfile)?;
+            }
+        }
         Ok(manifest_path)
     }
 
+    /// Remove the generated workspace, along with any hash directories it leaves empty
+    pub fn remove_workspace(
+        &self,
+        config: &cargo::Config,
+        target_dir: &cargo::util::Filesystem,
+    ) -> CargoResult<()> {
+        let workspace_root = self.workspace_root(target_dir.as_path_unlocked())?;
+        if workspace_root.exists() {
+            cargo_util::paths::remove_dir_all(&workspace_root)?;
+        }
+        crate::util::gc::prune_empty_parents(target_dir.as_path_unlocked(), &workspace_root);
+        crate::util::gc::forget(
+            config,
+            target_dir,
+            &self.workspace_key(target_dir.as_path_unlocked())?,
+        )
+    }
+
+    /// Identifies this version of the script's workspace in the [`crate::util::gc::Index`]
+    pub fn workspace_key(&self, target_dir: &std::path::Path) -> CargoResult<String> {
+        let workspace_root = self.workspace_root(target_dir)?;
+        let key = workspace_root
+            .strip_prefix(target_dir)
+            .expect("workspace is in the target directory")
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        Ok(key)
+    }
+
     pub fn expand_manifest(&self, config: &cargo::Config) -> CargoResult<String> {
-        let manifest = self
-            .expand_manifest_(config)
-            .with_context(|| format!("failed to parse manifest at {}", self.path.display()))?;
+        let manifest = self.expanded_manifest(config)?;
         let manifest = toml::to_string_pretty(&manifest)?;
         Ok(manifest)
     }
 
-    fn expand_manifest_(&self, config: &cargo::Config) -> CargoResult<toml::Table> {
-        let mut manifest: toml::Table = toml::from_str(&self.manifest)?;
+    /// The manifest cargo sees for this script, with relative paths resolved against the script
+    pub fn expanded_manifest(&self, config: &cargo::Config) -> CargoResult<toml::Table> {
+        let target_dir = self.target_dir(config)?;
+        let workspace_root = self.workspace_root(target_dir.as_path_unlocked())?;
+        let bin_path = self.bin_path(&workspace_root)?;
+        self.resolved_manifest(&bin_path, target_dir.as_path_unlocked(), config)
+    }
+
+    fn resolved_manifest(
+        &self,
+        bin_path: &std::path::Path,
+        target_dir: &std::path::Path,
+        config: &cargo::Config,
+    ) -> CargoResult<toml::Table> {
+        let manifest = self.expand_manifest_(bin_path, config)?;
+        let manifest = remap_paths(manifest, self.package_root()?)?;
+        let (manifest, _) = remap_script_dependencies(manifest, target_dir)?;
+        Ok(manifest)
+    }
+
+    /// Scripts this one depends on through `path`, directly or not, which are generated into
+    /// library packages of their own
+    pub(crate) fn script_dependencies(
+        &self,
+        target_dir: &std::path::Path,
+    ) -> CargoResult<Vec<RawScript>> {
+        let direct = |script: &RawScript| -> CargoResult<Vec<RawScript>> {
+            let manifest = toml::from_str::<toml::Table>(&script.manifest)
+                .map_err(|err| script.manifest_error(err.message(), err.span()))?;
+            let manifest = remap_paths(manifest, script.package_root()?)?;
+            let (_, scripts) = remap_script_dependencies(manifest, target_dir)?;
+            Ok(scripts)
+        };
+
+        let mut dependencies = Vec::<RawScript>::new();
+        let mut pending = direct(self)?;
+        while let Some(dependency) = pending.pop() {
+            // Cargo reports any cycles
+            let seen = dependency.id() == self.id()
+                || dependencies.iter().any(|d| d.id() == dependency.id());
+            if !seen {
+                pending.extend(direct(&dependency)?);
+                dependencies.push(dependency);
+            }
+        }
+        Ok(dependencies)
+    }
+
+    /// Where the script is built: cargo's configured target directory, or else that of the cargo
+    /// workspace it opted into, or else the one shared by all scripts
+    pub fn target_dir(&self, config: &cargo::Config) -> CargoResult<cargo::util::Filesystem> {
+        if let Some(target_dir) = config.target_dir()? {
+            return Ok(target_dir);
+        }
+        if let Some(root) = self.cargo_workspace()? {
+            return Ok(cargo::util::Filesystem::new(root.join("target")));
+        }
+        crate::config::default_target_dir().map(cargo::util::Filesystem::new)
+    }
+
+    /// Root of the cargo workspace the script opted into with `package.workspace = true`
+    pub fn cargo_workspace(&self) -> CargoResult<Option<std::path::PathBuf>> {
+        // Invalid manifests are reported when expanding them
+        let opted_in = toml::from_str::<toml::Table>(&self.manifest)
+            .ok()
+            .and_then(|manifest| manifest.get("package")?.get("workspace").cloned())
+            == Some(toml::Value::Boolean(true));
+        if !opted_in {
+            return Ok(None);
+        }
+        let script_dir = self.package_root()?;
+        for dir in script_dir.ancestors() {
+            let manifest_path = dir.join("Cargo.toml");
+            if !manifest_path.exists() {
+                continue;
+            }
+            let manifest = cargo_util::paths::read(&manifest_path)?
+                .parse::<toml::Table>()
+                .with_context(|| format!("failed to parse {}", manifest_path.display()))?;
+            let workspace = match manifest.get("workspace") {
+                Some(workspace) => workspace,
+                None => continue,
+            };
+            let paths = |key: &str| {
+                workspace
+                    .get(key)
+                    .and_then(|paths| paths.as_array())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|path| path.as_str())
+                    .map(|path| dir.join(path))
+                    .collect::<Vec<_>>()
+            };
+            // Like cargo, excluded scripts look for a workspace further up
+            let members = paths("members");
+            let excluded = paths("exclude")
+                .iter()
+                .any(|exclude| self.path.starts_with(exclude));
+            let explicit_member = members.iter().any(|member| self.path.starts_with(member));
+            if excluded && !explicit_member {
+                continue;
+            }
+
+            let mut is_member = script_dir == dir;
+            for member in &members {
+                let pattern = member.to_str().ok_or_else(|| {
+                    anyhow::format_err!(
+                        "`workspace.members` is not valid UTF-8: {}",
+                        member.display()
+                    )
+                })?;
+                let mut paths = glob::glob(pattern).with_context(|| {
+                    format!("invalid `workspace.members` in {}", manifest_path.display())
+                })?;
+                is_member |= paths.any(|path| {
+                    path.map(|path| path == script_dir || path == self.path)
+                        .unwrap_or(false)
+                });
+            }
+            if !is_member {
+                let relative = script_dir.strip_prefix(dir).unwrap_or(script_dir);
+                anyhow::bail!(
+                    "`{}` is not a member of the cargo workspace at `{}`
+
+help: add `{}` to `workspace.members`, or to `workspace.exclude` to keep it out",
+                    self.path.display(),
+                    dir.display(),
+                    relative.display()
+                );
+            }
+            return Ok(Some(dir.to_owned()));
+        }
+        anyhow::bail!(
+            "`package.workspace = true` requires `{}` to be in a cargo workspace",
+            self.path.display()
+        )
+    }
+
+    /// Where relative paths in the manifest are resolved from
+    fn package_root(&self) -> CargoResult<&std::path::Path> {
+        self.path
+            .parent()
+            .ok_or_else(|| anyhow::format_err!("no parent directory for {}", self.path.display()))
+    }
+
+    pub(crate) fn workspace_root(
+        &self,
+        target_dir: &std::path::Path,
+    ) -> CargoResult<std::path::PathBuf> {
+        let hash = self.id().to_string();
+        assert_eq!(hash.len(), 64);
+        let mut workspace_root = target_dir.to_owned();
+        workspace_root.push("eval");
+        workspace_root.push(&hash[0..2]);
+        workspace_root.push(&hash[2..4]);
+        workspace_root.push(&hash[4..]);
+        workspace_root.push(self.package_name()?);
+        Ok(workspace_root)
+    }
+
+    /// Features requested on the shebang line, like `#!/usr/bin/env -S cargo eval --features foo`
+    pub fn shebang_features(&self) -> Vec<String> {
+        let shebang = match tokenize(&self.body).next() {
+            Some(token) if token.kind == TokenKind::Shebang => token.text(&self.body),
+            _ => return Vec::new(),
+        };
+        let mut words = shebang.split_whitespace();
+        let mut features = Vec::new();
+        while let Some(word) = words.next() {
+            let value = if word == "--features" || word == "-F" {
+                words.next()
+            } else {
+                word.strip_prefix("--features=")
+                    .or_else(|| word.strip_prefix("-F"))
+            };
+            if let Some(value) = value {
+                features.extend(value.split(',').filter(|f| !f.is_empty()).map(String::from));
+            }
+        }
+        features
+    }
+
+    /// Extra arguments for rustc so diagnostics and panics point at the script, rather than the
+    /// sanitized copy that gets compiled
+    pub fn rustc_args(&self) -> CargoResult<Option<Vec<String>>> {
+        let copy = match self.copy_path()? {
+            Some(copy) => copy,
+            None => return Ok(None),
+        };
+        // cargo passes the copy to rustc relative to the workspace root, when inside of it
+        Ok(Some(vec![format!(
+            "--remap-path-prefix={}={}",
+            copy.display(),
+            self.path.display()
+        )]))
+    }
+
+    /// The file rustc compiles, either the script itself or a sanitized copy of it
+    fn bin_path(&self, workspace_root: &std::path::Path) -> CargoResult<std::path::PathBuf> {
+        match self.copy_path()? {
+            Some(copy) => Ok(workspace_root.join(copy)),
+            None => Ok(self.path.clone()),
+        }
+    }
+
+    /// Where the sanitized copy goes, if needed, relative to the workspace root
+    fn copy_path(&self) -> CargoResult<Option<std::path::PathBuf>> {
+        if self.source.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(copy_file_name(&self.bin_name()?).into()))
+    }
 
-        for key in ["workspace", "lib", "bin", "example", "test", "bench"] {
-            if manifest.contains_key(key) {
-                anyhow::bail!("`{key}` is not allowed in embedded manifests")
+    fn expand_manifest_(
+        &self,
+        bin_path: &std::path::Path,
+        config: &cargo::Config,
+    ) -> CargoResult<toml::Table> {
+        let spans: SpannedTable = toml::from_str(&self.manifest)
+            .map_err(|err| self.manifest_error(err.message(), err.span()))?;
+        for key in spans.keys() {
+            let key_name = key.get_ref().as_str();
+            if ["workspace", "bin", "example", "test", "bench"].contains(&key_name) {
+                return Err(self.manifest_error(
+                    &format!("`{key_name}` is not allowed in embedded manifests"),
+                    Some(key.span()),
+                ));
+            }
+            let is_empty_table = |value: &toml::Value| {
+                value
+                    .as_table()
+                    .map(|table| table.is_empty())
+                    .unwrap_or(false)
+            };
+            if key_name == "lib" && !spans.get(key).map(is_empty_table).unwrap_or(false) {
+                return Err(self.manifest_error(
+                    "`lib` must be empty in embedded manifests",
+                    Some(key.span()),
+                ));
             }
         }
+        let mut manifest = spans
+            .iter()
+            .map(|(key, value)| (key.get_ref().clone(), value.clone()))
+            .collect::<toml::Table>();
 
         // Prevent looking for a workspace by `read_manifest_from_str`
         manifest.insert("workspace".to_owned(), toml::Table::new().into());
 
+        let package_span = spans
+            .keys()
+            .find(|key| key.get_ref() == "package")
+            .map(|key| key.span());
         let package = manifest
             .entry("package".to_owned())
             .or_insert_with(|| toml::Table::new().into())
             .as_table_mut()
-            .ok_or_else(|| anyhow::format_err!("`package` must be a table"))?;
-        for key in ["workspace", "build", "links"] {
-            if package.contains_key(key) {
-                anyhow::bail!("`package.{key}` is not allowed in embedded manifests")
+            .ok_or_else(|| {
+                self.manifest_error("`package` must be a table", package_span.clone())
+            })?;
+        let package_spans: PackageSpans = toml::from_str(&self.manifest)
+            .map_err(|err| self.manifest_error(err.message(), err.span()))?;
+        for key in package_spans.package.keys() {
+            let key_name = key.get_ref().as_str();
+            if ["build", "links"].contains(&key_name) {
+                return Err(self.manifest_error(
+                    &format!("`package.{key_name}` is not allowed in embedded manifests"),
+                    Some(key.span()),
+                ));
+            }
+            if key_name == "workspace"
+                && package.get("workspace") != Some(&toml::Value::Boolean(true))
+            {
+                return Err(self.manifest_error(
+                    "`package.workspace` must be `true` in embedded manifests",
+                    Some(key.span()),
+                ));
             }
         }
+        // Membership is emulated, with the generated package as its own workspace
+        package.remove("workspace");
         let name = self.package_name()?;
-        let hash = self.hash();
-        let bin_name = format!("{name}_{hash}");
+        let bin_name = self.bin_name()?;
         package
             .entry("name".to_owned())
             .or_insert(toml::Value::String(name));
@@ -147,31 +652,47 @@ impl RawScript {
             .entry("publish".to_owned())
             .or_insert_with(|| toml::Value::Boolean(DEFAULT_PUBLISH));
 
-        let mut bin = toml::Table::new();
-        bin.insert("name".to_owned(), toml::Value::String(bin_name));
-        bin.insert(
-            "path".to_owned(),
-            toml::Value::String(
-                self.path
-                    .to_str()
-                    .ok_or_else(|| anyhow::format_err!("path is not valid UTF-8"))?
-                    .into(),
-            ),
-        );
-        manifest.insert(
-            "bin".to_owned(),
-            toml::Value::Array(vec![toml::Value::Table(bin)]),
+        let path = toml::Value::String(
+            bin_path
+                .to_str()
+                .ok_or_else(|| anyhow::format_err!("path is not valid UTF-8"))?
+                .into(),
         );
+        if self.lib {
+            // Named after the package, for use in doc-tests
+            let mut lib = toml::Table::new();
+            lib.insert("path".to_owned(), path);
+            manifest.insert("lib".to_owned(), toml::Value::Table(lib));
+        } else {
+            let mut bin = toml::Table::new();
+            bin.insert("name".to_owned(), toml::Value::String(bin_name));
+            bin.insert("path".to_owned(), path);
+            manifest.insert(
+                "bin".to_owned(),
+                toml::Value::Array(vec![toml::Value::Table(bin)]),
+            );
+        }
 
+        if let Some(root) = self.cargo_workspace()? {
+            let workspace = inherit_workspace(&mut manifest, &root)?;
+            manifest.insert("workspace".to_owned(), toml::Value::Table(workspace));
+        }
+
+        let profile_span = spans
+            .keys()
+            .find(|key| key.get_ref() == "profile")
+            .map(|key| key.span());
         let release = manifest
             .entry("profile".to_owned())
             .or_insert_with(|| toml::Value::Table(Default::default()))
             .as_table_mut()
-            .ok_or_else(|| anyhow::format_err!("`profile` must be a table"))?
+            .ok_or_else(|| self.manifest_error("`profile` must be a table", profile_span.clone()))?
             .entry("release".to_owned())
             .or_insert_with(|| toml::Value::Table(Default::default()))
             .as_table_mut()
-            .ok_or_else(|| anyhow::format_err!("`profile.release` must be a table"))?;
+            .ok_or_else(|| {
+                self.manifest_error("`profile.release` must be a table", profile_span.clone())
+            })?;
         release
             .entry("strip".to_owned())
             .or_insert_with(|| toml::Value::Boolean(true));
@@ -179,6 +700,51 @@ impl RawScript {
         Ok(manifest)
     }
 
+    /// Reports a problem with the embedded manifest, pointing at the offending line of the script
+    fn manifest_error(&self, message: &str, span: Option<std::ops::Range<usize>>) -> anyhow::Error {
+        use annotate_snippets::display_list::DisplayList;
+        use annotate_snippets::snippet::{AnnotationType, Slice, Snippet, SourceAnnotation};
+
+        let path = self.path.display().to_string();
+        let span = match span {
+            Some(span) => span,
+            None => return anyhow::format_err!("{message}\n --> {path}"),
+        };
+        let start = self.manifest_map.to_script(span.start).min(self.body.len());
+        let end = self
+            .manifest_map
+            .to_script(span.end.max(span.start))
+            .max(start);
+        let line_start = self.body[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = self.body[start..]
+            .find('\n')
+            .map(|i| start + i)
+            .unwrap_or(self.body.len());
+        let line = self.body[line_start..line_end].trim_end_matches('\r');
+        let end = end.min(line_start + line.len());
+        let line_number = self.body[..line_start].matches('\n').count() + 1;
+        let column_start = self.body[line_start..start].chars().count();
+        let column_end = column_start + self.body[start..end].chars().count().max(1);
+
+        let snippet = Snippet {
+            title: None,
+            footer: vec![],
+            slices: vec![Slice {
+                source: line,
+                line_start: line_number,
+                origin: Some(&path),
+                annotations: vec![SourceAnnotation {
+                    range: (column_start, column_end),
+                    label: "",
+                    annotation_type: AnnotationType::Error,
+                }],
+                fold: false,
+            }],
+            opt: Default::default(),
+        };
+        anyhow::format_err!("{message}\n{}", DisplayList::from(snippet))
+    }
+
     fn package_name(&self) -> CargoResult<String> {
         let name = self
             .path
@@ -207,222 +773,985 @@ impl RawScript {
         Ok(slug)
     }
 
-    fn hash(&self) -> blake3::Hash {
-        blake3::hash(self.body.as_bytes())
-    }
-}
-
-/// Locates a "code block manifest" in Rust source.
-fn extract_comment(input: &str) -> CargoResult<String> {
-    let re_crate_comment = regex::Regex::new(
-        // We need to find the first `/*!` or `//!` that *isn't* preceded by something that would
-        // make it apply to anything other than the crate itself.  Because we can't do this
-        // accurately, we'll just require that the doc-comment is the *first* thing in the file
-        // (after the optional shebang).
-        r"(?x)(^\s*|^\#![^\[].*?(\r\n|\n))(/\*!|//(!|/))",
-    )
-    .unwrap();
-    let re_margin = regex::Regex::new(r"^\s*\*( |$)").unwrap();
-    let re_space = regex::Regex::new(r"^(\s+)").unwrap();
-    let re_nesting = regex::Regex::new(r"/\*|\*/").unwrap();
-    let re_comment = regex::Regex::new(r"^\s*//(!|/)").unwrap();
-
-    fn n_leading_spaces(s: &str, n: usize) -> anyhow::Result<()> {
-        if !s.chars().take(n).all(|c| c == ' ') {
-            anyhow::bail!("leading {n:?} chars aren't all spaces: {s:?}")
+    /// Link the binary cargo built into the script's workspace under the script's own name, so
+    /// `ps`, `top`, and `current_exe` don't show the hashed name that keeps it apart from other
+    /// scripts
+    pub(crate) fn link_bin(
+        &self,
+        target_dir: &std::path::Path,
+        built: &std::path::Path,
+    ) -> CargoResult<std::path::PathBuf> {
+        let built_dir = built
+            .parent()
+            .ok_or_else(|| anyhow::format_err!("no parent directory for {}", built.display()))?;
+        // Mirror the layout of the target directory, keeping profiles and targets apart
+        let built_dir = built_dir
+            .strip_prefix(target_dir)
+            .unwrap_or_else(|_| std::path::Path::new(built_dir.file_name().unwrap_or_default()));
+        let mut file_name = std::ffi::OsString::from(self.package_name()?);
+        if let Some(extension) = built.extension() {
+            file_name.push(".");
+            file_name.push(extension);
         }
-        Ok(())
+        let link = self
+            .workspace_root(target_dir)?
+            .join("bin")
+            .join(built_dir)
+            .join(file_name);
+        cargo_util::paths::create_dir_all(link.parent().expect("always has a file name"))?;
+        cargo_util::paths::link_or_copy(built, &link)?;
+        Ok(link)
     }
 
-    /// Returns a slice of the input string with the leading shebang, if there is one, omitted.
-    fn strip_shebang(s: &str) -> &str {
-        let re_shebang = regex::Regex::new(r"^#![^\[].*?(\r\n|\n)").unwrap();
-        re_shebang.find(s).map(|m| &s[m.end()..]).unwrap_or(s)
-    }
-
-    // First, we will look for and slice out a contiguous, inner doc-comment which must be *the
-    // very first thing* in the file.  `#[doc(...)]` attributes *are not supported*.  Multiple
-    // single-line comments cannot have any blank lines between them.
-    let input = strip_shebang(input); // `re_crate_comment` doesn't work with shebangs
-    let start = re_crate_comment
-        .captures(input)
-        .ok_or_else(|| anyhow::format_err!("no doc-comment found"))?
-        .get(3)
-        .ok_or_else(|| anyhow::format_err!("no doc-comment found"))?
-        .start();
-
-    let input = &input[start..];
-
-    if let Some(input) = input.strip_prefix("/*!") {
-        // On every line:
-        //
-        // - update nesting level and detect end-of-comment
-        // - if margin is None:
-        //     - if there appears to be a margin, set margin.
-        // - strip off margin marker
-        // - update the leading space counter
-        // - strip leading space
-        // - append content
-        let mut r = String::new();
-
-        let mut leading_space = None;
-        let mut margin = None;
-        let mut depth: u32 = 1;
-
-        for line in input.lines() {
-            if depth == 0 {
-                break;
-            }
+    /// `argv[0]` for running the script, unless `process` runs `bin` through a runner
+    pub(crate) fn arg0(
+        &self,
+        bin: &std::path::Path,
+        process: &cargo_util::ProcessBuilder,
+    ) -> Option<std::ffi::OsString> {
+        (std::path::Path::new(process.get_program()) == bin).then(|| self.path.clone().into())
+    }
 
-            // Update nesting and look for end-of-comment.
-            let mut end_of_comment = None;
+    pub(crate) fn bin_name(&self) -> CargoResult<String> {
+        Ok(format!("{}_{}", self.package_name()?, self.id()))
+    }
 
-            for (end, marker) in re_nesting.find_iter(line).map(|m| (m.start(), m.as_str())) {
-                match (marker, depth) {
-                    ("/*", _) => depth += 1,
-                    ("*/", 1) => {
-                        end_of_comment = Some(end);
-                        depth = 0;
-                        break;
-                    }
-                    ("*/", _) => depth -= 1,
-                    _ => panic!("got a comment marker other than /* or */"),
-                }
-            }
+    /// Identifies the script's workspace and binary, so they get reused as the script is edited
+    fn id(&self) -> blake3::Hash {
+        let path = dunce::canonicalize(&self.path).unwrap_or_else(|_| self.path.clone());
+        blake3::hash(path.to_string_lossy().as_bytes())
+    }
 
-            let line = end_of_comment.map(|end| &line[..end]).unwrap_or(line);
+    /// Content of the script, to tell versions apart
+    pub(crate) fn hash(&self) -> blake3::Hash {
+        blake3::hash(self.body.as_bytes())
+    }
+}
 
-            // Detect and strip margin.
-            margin = margin.or_else(|| re_margin.find(line).map(|m| m.as_str()));
+// `toml` can't track the spans of values in tables from dotted headers, like `[profile.fast]`
+type SpannedTable = std::collections::BTreeMap<toml::Spanned<String>, toml::Value>;
 
-            let line = if let Some(margin) = margin {
-                let end = line
-                    .char_indices()
-                    .take(margin.len())
-                    .map(|(i, c)| i + c.len_utf8())
-                    .last()
-                    .unwrap_or(0);
-                &line[end..]
-            } else {
-                line
-            };
+/// Locations of `package` keys, for validation errors
+#[derive(serde::Deserialize)]
+struct PackageSpans {
+    #[serde(default)]
+    package: std::collections::BTreeMap<toml::Spanned<String>, serde::de::IgnoredAny>,
+}
 
-            // Detect and strip leading indentation.
-            leading_space = leading_space.or_else(|| re_space.find(line).map(|m| m.end()));
+/// Maps byte offsets in extracted text back to the text it was extracted from
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct SourceMap {
+    /// `(extracted offset, original offset)` for the start of each contiguous run
+    segments: Vec<(usize, usize)>,
+}
 
-            // Make sure we have only leading spaces.
-            //
-            // If we see a tab, fall over.  I *would* expand them, but that gets into the question of how *many* spaces to expand them to, and *where* is the tab, because tabs are tab stops and not just N spaces.
-            n_leading_spaces(line, leading_space.unwrap_or(0))?;
+impl SourceMap {
+    fn contiguous(original: usize) -> Self {
+        Self {
+            segments: vec![(0, original)],
+        }
+    }
 
-            let strip_len = line.len().min(leading_space.unwrap_or(0));
-            let line = &line[strip_len..];
+    fn push(&mut self, extracted: usize, original: usize) {
+        self.segments.push((extracted, original));
+    }
 
-            // Done.
-            r.push_str(line);
+    fn to_script(&self, extracted: usize) -> usize {
+        let i = self.segments.partition_point(|(e, _)| *e <= extracted);
+        match i.checked_sub(1).map(|i| self.segments[i]) {
+            Some((e, o)) => o + (extracted - e),
+            None => extracted,
+        }
+    }
 
-            // `lines` removes newlines.  Ideally, it wouldn't do that, but hopefully this shouldn't cause any *real* problems.
-            r.push('\n');
+    /// Maps through `self` and then through `outer`
+    fn compose(&self, outer: &SourceMap) -> SourceMap {
+        let mut composed = SourceMap::default();
+        for (i, (extracted, middle)) in self.segments.iter().copied().enumerate() {
+            composed.push(extracted, outer.to_script(middle));
+            // Split wherever `outer` is discontiguous within this segment
+            let middle_end = self
+                .segments
+                .get(i + 1)
+                .map(|(next, _)| middle + (next - extracted))
+                .unwrap_or(usize::MAX);
+            for (outer_start, original) in outer.segments.iter().copied() {
+                if middle < outer_start && outer_start < middle_end {
+                    composed.push(extracted + (outer_start - middle), original);
+                }
+            }
         }
+        composed
+    }
+}
 
-        Ok(r)
-    } else if input.starts_with("//!") || input.starts_with("///") {
-        let mut r = String::new();
+/// Byte offset of `sub` within `input`, where `sub` must be a sub-slice of `input`
+fn offset_of(input: &str, sub: &str) -> usize {
+    sub.as_ptr() as usize - input.as_ptr() as usize
+}
 
-        let mut leading_space = None;
+/// Replaces `span` with spaces, keeping line and column numbers the same for rustc
+fn blank(input: &str, span: std::ops::Range<usize>) -> String {
+    let mut source = String::with_capacity(input.len());
+    source.push_str(&input[..span.start]);
+    source.extend(
+        input[span.clone()]
+            .chars()
+            .map(|c| if c == '\r' || c == '\n' { c } else { ' ' }),
+    );
+    source.push_str(&input[span.end..]);
+    source
+}
 
-        for line in input.lines() {
-            // Strip leading comment marker.
-            let content = match re_comment.find(line) {
-                Some(m) => &line[m.end()..],
-                None => break,
-            };
+/// Locates a `---cargo` frontmatter manifest, right after the optional shebang.
+///
+/// Returns the manifest and the source with the frontmatter blanked out so line numbers are
+/// preserved for rustc.
+fn extract_frontmatter(input: &str) -> CargoResult<Option<((String, SourceMap), String)>> {
+    let mut lines = input.split_inclusive('\n').peekable();
+    if let Some(line) = lines.peek() {
+        if line.starts_with("#!") && !line.starts_with("#![") {
+            lines.next();
+        }
+    }
+    while let Some(line) = lines.peek() {
+        if line.trim().is_empty() {
+            lines.next();
+        } else {
+            break;
+        }
+    }
 
-            // Detect and strip leading indentation.
-            leading_space = leading_space.or_else(|| {
-                re_space
-                    .captures(content)
-                    .and_then(|c| c.get(1))
-                    .map(|m| m.end())
-            });
+    let open = match lines.next() {
+        Some(line) if line.starts_with("---") => line,
+        _ => return Ok(None),
+    };
+    let fence = &open[0..open.find(|c| c != '-').unwrap_or(open.len())];
+    let info = open[fence.len()..].trim();
+    if !info.is_empty() && info != "cargo" {
+        anyhow::bail!("unsupported frontmatter infostring `{info}`; expected `cargo`");
+    }
 
-            // Make sure we have only leading spaces.
-            //
-            // If we see a tab, fall over.  I *would* expand them, but that gets into the question of how *many* spaces to expand them to, and *where* is the tab, because tabs are tab stops and not just N spaces.
-            n_leading_spaces(content, leading_space.unwrap_or(0))?;
+    let manifest_start = offset_of(input, open) + open.len();
+    let mut manifest_end = manifest_start;
+    let mut close = None;
+    for line in lines.by_ref() {
+        if line.trim_end() == fence {
+            close = Some(line);
+            break;
+        }
+        manifest_end += line.len();
+    }
+    let close =
+        close.ok_or_else(|| anyhow::format_err!("unclosed frontmatter; expected `{fence}`"))?;
 
-            let strip_len = content.len().min(leading_space.unwrap_or(0));
-            let content = &content[strip_len..];
+    let manifest = input[manifest_start..manifest_end].to_owned();
+    let span = offset_of(input, open)..offset_of(input, close) + close.len();
+    let source = blank(input, span);
 
-            // Done.
-            r.push_str(content);
+    Ok(Some((
+        (manifest, SourceMap::contiguous(manifest_start)),
+        source,
+    )))
+}
 
-            // `lines` removes newlines.  Ideally, it wouldn't do that, but hopefully this shouldn't cause any *real* problems.
-            r.push('\n');
-        }
+/// Crate-level items at the top of a script
+enum PreludeItem {
+    /// `#![...]` with the tokens inside the brackets
+    Attribute {
+        span: std::ops::Range<usize>,
+        group: Vec<Token>,
+    },
+    DocComment(Token),
+}
 
-        Ok(r)
-    } else {
-        Err(anyhow::format_err!("no doc-comment found"))
+/// Collects the inner attributes and doc-comments that precede the first item of a script
+///
+/// The shebang, whitespace, and regular comments are skipped.
+fn prelude(input: &str) -> CargoResult<Vec<PreludeItem>> {
+    let mut tokens = tokenize(input).peekable();
+    let mut items = Vec::new();
+    while let Some(token) = tokens.peek().cloned() {
+        match token.kind {
+            TokenKind::BlockComment {
+                terminated: false, ..
+            } => {
+                anyhow::bail!("unterminated block comment")
+            }
+            TokenKind::LineComment { doc: Some(_) }
+            | TokenKind::BlockComment { doc: Some(_), .. } => {
+                tokens.next();
+                items.push(PreludeItem::DocComment(token));
+            }
+            TokenKind::Punct('#') => {
+                let mut lookahead = tokens.clone().skip(1).filter(|t| !t.is_trivia());
+                let is_inner_attribute = lookahead.next().map(|t| t.kind)
+                    == Some(TokenKind::Punct('!'))
+                    && lookahead.next().map(|t| t.kind) == Some(TokenKind::Punct('['));
+                if !is_inner_attribute {
+                    break;
+                }
+                let (group, close) = take_group(&mut lookahead, '[', ']')
+                    .ok_or_else(|| anyhow::format_err!("unclosed inner attribute"))?;
+                let span = token.span.start..close.span.end;
+                while tokens.peek().map(|t| t.span.start < span.end) == Some(true) {
+                    tokens.next();
+                }
+                items.push(PreludeItem::Attribute { span, group });
+            }
+            _ if token.kind == TokenKind::Shebang || token.is_trivia() => {
+                tokens.next();
+            }
+            _ => break,
+        }
     }
+    Ok(items)
 }
 
-/// Extracts the first `Cargo` fenced code block from a chunk of Markdown.
-fn extract_manifest(comment: &str) -> CargoResult<Option<String>> {
-    use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
+/// Locates a `#![cargo(manifest = r#"..."#)]` attribute manifest.
+///
+/// Returns the manifest and the source with the attribute blanked out so line and column numbers
+/// are preserved for rustc.
+fn extract_attribute(input: &str) -> CargoResult<Option<((String, SourceMap), String)>> {
+    let mut attributes = prelude(input)?.into_iter().filter_map(|item| match item {
+        PreludeItem::Attribute { span, group } => {
+            let group = group
+                .into_iter()
+                .filter(|t| !t.is_trivia())
+                .collect::<Vec<_>>();
+            let is_cargo = group.first().map(|t| t.text(input)) == Some("cargo");
+            is_cargo.then_some((span, group))
+        }
+        PreludeItem::DocComment(_) => None,
+    });
+    let (span, group) = match attributes.next() {
+        Some(attribute) => attribute,
+        None => return Ok(None),
+    };
+    if attributes.next().is_some() {
+        anyhow::bail!("multiple `cargo` attributes present");
+    }
 
-    // To match librustdoc/html/markdown.rs, opts.
-    let exts = Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES;
+    let invalid = || anyhow::format_err!(r##"expected `#![cargo(manifest = r#"..."#)]`"##);
+    let manifest = match group.as_slice() {
+        [_cargo, open, key, eq, value, close]
+            if open.kind == TokenKind::Punct('(')
+                && key.text(input) == "manifest"
+                && eq.kind == TokenKind::Punct('=')
+                && close.kind == TokenKind::Punct(')') =>
+        {
+            match value.kind {
+                TokenKind::Literal {
+                    kind: LiteralKind::RawStr { hashes },
+                    terminated: true,
+                } if value.text(input).starts_with('r') => {
+                    let text = value.text(input);
+                    &text[1 + hashes + 1..text.len() - 1 - hashes]
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        _ => return Err(invalid()),
+    };
 
-    let md = Parser::new_ext(comment, exts);
+    let source = blank(input, span);
+    let map = SourceMap::contiguous(offset_of(input, manifest));
 
-    let mut inside = false;
-    let mut output = None;
+    Ok(Some(((manifest.to_owned(), map), source)))
+}
 
-    for item in md {
-        match item {
-            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info)))
-                if info.to_lowercase() == "cargo" =>
-            {
-                if output.is_some() {
-                    anyhow::bail!("multiple `cargo` manifests present")
-                } else {
-                    output = Some(String::new());
+/// Whether the script only defines items, having neither a `fn main` nor statements to wrap in one
+fn is_library(input: &str) -> bool {
+    const ITEM_KEYWORDS: &[&str] = &[
+        "const",
+        "enum",
+        "extern",
+        "fn",
+        "impl",
+        "macro_rules",
+        "mod",
+        "pub",
+        "static",
+        "struct",
+        "trait",
+        "type",
+        "union",
+        "use",
+    ];
+    const QUALIFIED_ITEM_KEYWORDS: &[&str] = &["fn", "impl", "trait", "extern", "mod"];
+
+    let mut has_items = false;
+    let mut tokens = tokenize(input).filter(|t| !t.is_trivia()).peekable();
+    while let Some(token) = tokens.next() {
+        let is_item = match token.text(input) {
+            // Like `unsafe fn`, rather than an `unsafe { .. }` block or `async move { .. }`
+            "async" | "unsafe" => {
+                while tokens
+                    .peek()
+                    .map_or(false, |t| ["async", "unsafe"].contains(&t.text(input)))
+                {
+                    tokens.next();
                 }
-                inside = true;
+                tokens
+                    .peek()
+                    .map_or(false, |t| QUALIFIED_ITEM_KEYWORDS.contains(&t.text(input)))
             }
-            Event::Text(ref text) if inside => {
-                let s = output.get_or_insert(String::new());
-                s.push_str(text);
+            text => ITEM_KEYWORDS.contains(&text),
+        };
+        match token.kind {
+            TokenKind::Shebang | TokenKind::LineComment { .. } | TokenKind::BlockComment { .. } => {
             }
-            Event::End(Tag::CodeBlock(_)) if inside => {
-                inside = false;
+            TokenKind::Punct('#') => {
+                if tokens.peek().map(|t| t.kind) == Some(TokenKind::Punct('!')) {
+                    tokens.next();
+                }
+                if tokens.next().map(|t| t.kind) != Some(TokenKind::Punct('['))
+                    || take_group(&mut tokens, '[', ']').is_none()
+                {
+                    return false;
+                }
             }
-            _ => (),
+            TokenKind::Ident if is_item => {
+                // Skip to the `;` or `}` ending the item
+                let mut depth = 0_usize;
+                let mut prev = token;
+                for token in tokens.by_ref() {
+                    match token.kind {
+                        TokenKind::Punct('(' | '[' | '{') => depth += 1,
+                        TokenKind::Punct(')' | ']') => depth = depth.saturating_sub(1),
+                        TokenKind::Punct('}') => {
+                            depth = depth.saturating_sub(1);
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        TokenKind::Punct(';') if depth == 0 => break,
+                        TokenKind::Ident
+                            if depth == 0
+                                && prev.text(input) == "fn"
+                                && token.text(input) == "main" =>
+                        {
+                            return false;
+                        }
+                        _ => {}
+                    }
+                    prev = token;
+                }
+                // Like `use std::{io, fs};` or `const ORIGIN: Point = Point { x: 0, y: 0 };`
+                if tokens.peek().map(|t| t.kind) == Some(TokenKind::Punct(';')) {
+                    tokens.next();
+                }
+                has_items = true;
+            }
+            _ => return false,
         }
     }
+    has_items
+}
 
-    Ok(output)
+/// Whether the script refers to files relative to itself, through `mod foo;` or `include!` and the
+/// like
+fn uses_relative_paths(input: &str) -> bool {
+    let tokens = tokenize(input)
+        .filter(|t| !t.is_trivia())
+        .collect::<Vec<_>>();
+    tokens.windows(3).any(|window| match window {
+        [keyword, name, semi] if keyword.text(input) == "mod" => {
+            name.kind == TokenKind::Ident && semi.kind == TokenKind::Punct(';')
+        }
+        [name, bang, _] if bang.kind == TokenKind::Punct('!') => {
+            ["include", "include_str", "include_bytes"].contains(&name.text(input))
+        }
+        _ => false,
+    })
 }
 
-#[cfg(test)]
-mod test_expand {
-    use super::*;
+/// Whether a module's file declares file modules of its own, like `mod foo;`
+fn declares_file_modules(input: &str) -> bool {
+    let tokens = tokenize(input)
+        .filter(|t| !t.is_trivia())
+        .collect::<Vec<_>>();
+    tokens.windows(3).any(|window| match window {
+        [keyword, name, semi] => {
+            keyword.text(input) == "mod"
+                && name.kind == TokenKind::Ident
+                && semi.kind == TokenKind::Punct(';')
+        }
+        _ => false,
+    })
+}
 
-    macro_rules! si {
-        ($i:expr) => {
-            RawScript::parse($i, std::path::Path::new("/home/me/test.rs"))
-                .unwrap_or_else(|err| panic!("{}", err))
-                .expand_manifest(&cargo::util::Config::default().unwrap())
-                .unwrap_or_else(|err| panic!("{}", err))
-        };
-    }
+/// File name of the sanitized copy in the script's workspace
+///
+/// cargo passes it to rustc relative to the workspace root, and rustc records it that way in
+/// dep-info, so it is named to tell scripts apart there.
+pub(crate) fn copy_file_name(bin_name: &str) -> String {
+    format!("{bin_name}.rs")
+}
+
+/// Points `mod foo;` and `include!` and the like at the files next to the script, so they are found
+/// from the sanitized copy in its workspace
+///
+/// File modules at the top level get an absolute `#[path]`, or have theirs made absolute, and
+/// relative string literals passed to `include!`, `include_str!`, and `include_bytes!` are made
+/// absolute.  Nothing is added or removed on other lines, so line numbers are preserved for rustc.
+///
+/// rustc looks up the file modules of a file loaded through `#[path]` next to it, like for
+/// `mod.rs`, rather than in a directory named after it.  So a `foo.rs` declaring its own is
+/// instead declared in an inline module pointing at the script's directory, which sees the same
+/// items through `super::`, and imported from there.
+fn absolute_paths(input: &str, dir: &std::path::Path) -> CargoResult<String> {
+    let tokens = tokenize(input)
+        .filter(|t| !t.is_trivia())
+        .collect::<Vec<_>>();
+    let literal = |path: &std::path::Path| -> CargoResult<String> {
+        let path = path
+            .to_str()
+            .ok_or_else(|| anyhow::format_err!("path is not valid UTF-8"))?;
+        Ok(format!("{path:?}"))
+    };
+
+    let mut edits = Vec::new();
+    let mut depth = 0_usize;
+    // Where the current top-level item starts, after its attributes and doc-comments
+    let mut item_start = None;
+    // The `#[path = "..."]` literal for the current top-level item
+    let mut path_attribute = None;
+    // The `#[cfg(...)]`-like attributes of the current top-level item
+    let mut cfg_attributes = Vec::new();
+    let mut i = 0;
+    while let Some(token) = tokens.get(i) {
+        match token.kind {
+            TokenKind::Punct('#') if depth == 0 => {
+                let close = attribute_end(&tokens, i)?;
+                if let [_, open, key, eq, value, _] = &tokens[i..=close] {
+                    if open.kind == TokenKind::Punct('[')
+                        && key.text(input) == "path"
+                        && eq.kind == TokenKind::Punct('=')
+                    {
+                        path_attribute = Some(value.clone());
+                    }
+                }
+                if tokens.get(i + 1).map(|t| t.kind) == Some(TokenKind::Punct('['))
+                    && ["cfg", "cfg_attr"].contains(&tokens[i + 2].text(input))
+                {
+                    cfg_attributes.push(&input[token.span.start..tokens[close].span.end]);
+                }
+                i = close;
+            }
+            TokenKind::Punct('(' | '[' | '{') => depth += 1,
+            TokenKind::Punct(')' | ']') => depth = depth.saturating_sub(1),
+            TokenKind::Punct('}') => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    item_start = None;
+                    path_attribute = None;
+                    cfg_attributes.clear();
+                }
+            }
+            TokenKind::Punct(';') if depth == 0 => {
+                item_start = None;
+                path_attribute = None;
+                cfg_attributes.clear();
+            }
+            TokenKind::Ident
+                if depth == 0
+                    && token.text(input) == "mod"
+                    && tokens.get(i + 1).map(|t| t.kind) == Some(TokenKind::Ident) =>
+            {
+                let start = item_start.unwrap_or(token.span.start);
+                match path_attribute.take() {
+                    Some(value) => {
+                        if let Some(path) = literal_path(input, &value) {
+                            if path.is_relative() {
+                                edits.push((value.span, literal(&dir.join(path))?));
+                            }
+                        }
+                    }
+                    None => {
+                        let name = tokens[i + 1].text(input);
+                        let name = name.strip_prefix("r#").unwrap_or(name);
+                        let path = match tokens.get(i + 2).map(|t| t.kind) {
+                            // Nested file modules are found in a directory named after it
+                            Some(TokenKind::Punct('{')) => dir.join(name),
+                            _ => {
+                                let file = dir.join(format!("{name}.rs"));
+                                let mod_rs = dir.join(name).join("mod.rs");
+                                if !file.exists() && mod_rs.exists() {
+                                    mod_rs
+                                } else {
+                                    file
+                                }
+                            }
+                        };
+                        let has_file_modules = path.file_name() != Some("mod.rs".as_ref())
+                            && std::fs::read_to_string(&path)
+                                .map(|module| declares_file_modules(&module))
+                                .unwrap_or(false);
+                        match tokens.get(i + 2).filter(|_| has_file_modules) {
+                            Some(semi) => {
+                                // Any visibility, with its trailing space
+                                let visibility = &input[start..token.span.start];
+                                let cfg_attributes = cfg_attributes
+                                    .iter()
+                                    .map(|attribute| format!("{attribute} "))
+                                    .collect::<String>();
+                                let wrapper = format!("__cargo_eval_{name}");
+                                let item = format!(
+                                    concat!(
+                                        "#[path = {dir}] mod {wrapper} {{ ",
+                                        "#[allow(unused_imports)] use super::*; ",
+                                        "#[macro_use] pub mod {name}; ",
+                                        "}} ",
+                                        "{cfg_attributes}#[allow(unused_imports)] ",
+                                        "{visibility}use self::{wrapper}::{name};",
+                                    ),
+                                    dir = literal(dir)?,
+                                    wrapper = wrapper,
+                                    name = name,
+                                    cfg_attributes = cfg_attributes,
+                                    visibility = visibility,
+                                );
+                                edits.push((start..semi.span.end, item));
+                            }
+                            None => {
+                                edits.push((
+                                    start..start,
+                                    format!("#[path = {}] ", literal(&path)?),
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+            TokenKind::Ident
+                if ["include", "include_str", "include_bytes"].contains(&token.text(input)) =>
+            {
+                let is_call = tokens.get(i + 1).map(|t| t.kind) == Some(TokenKind::Punct('!'))
+                    && matches!(
+                        tokens.get(i + 2).map(|t| t.kind),
+                        Some(TokenKind::Punct('(' | '[' | '{'))
+                    );
+                if let Some(value) = tokens.get(i + 3).filter(|_| is_call) {
+                    if let Some(path) = literal_path(input, value) {
+                        if path.is_relative() {
+                            edits.push((value.span.clone(), literal(&dir.join(path))?));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        let is_item_prefix = matches!(
+            token.kind,
+            TokenKind::Punct('#' | ';' | '}')
+                | TokenKind::LineComment { doc: Some(_) }
+                | TokenKind::BlockComment { doc: Some(_), .. }
+        );
+        if depth == 0 && item_start.is_none() && !is_item_prefix {
+            item_start = Some(token.span.start);
+        }
+        i += 1;
+    }
+
+    let mut source = input.to_owned();
+    for (span, replacement) in edits.into_iter().rev() {
+        source.replace_range(span, &replacement);
+    }
+    Ok(source)
+}
+
+/// The path in a string literal, unless it has escapes
+fn literal_path<'i>(input: &'i str, token: &Token) -> Option<&'i std::path::Path> {
+    let text = token.text(input);
+    let path = match token.kind {
+        TokenKind::Literal {
+            kind: LiteralKind::Str,
+            terminated: true,
+        } => text
+            .strip_prefix('"')?
+            .strip_suffix('"')
+            .filter(|path| !path.contains('\\'))?,
+        TokenKind::Literal {
+            kind: LiteralKind::RawStr { hashes },
+            terminated: true,
+        } if text.starts_with('r') => &text[1 + hashes + 1..text.len() - 1 - hashes],
+        _ => return None,
+    };
+    Some(std::path::Path::new(path))
+}
+
+/// Wraps top-level statements in a generated `fn main`, if the script doesn't have one
+///
+/// Inner attributes, `use`, `mod`, and `extern crate` items are kept at module scope.
+/// `fn main() {` is inserted without adding lines so line numbers are preserved for rustc.
+fn implicit_main(input: &str) -> CargoResult<Option<String>> {
+    let tokens = tokenize(input)
+        .filter(|t| !t.is_trivia())
+        .collect::<Vec<_>>();
+
+    let mut depth = 0_usize;
+    for (token, next) in tokens.iter().zip(tokens.iter().skip(1)) {
+        match token.kind {
+            TokenKind::Punct('(' | '[' | '{') => depth += 1,
+            TokenKind::Punct(')' | ']' | '}') => depth = depth.saturating_sub(1),
+            TokenKind::Ident
+                if depth == 0
+                    && token.text(input) == "fn"
+                    && next.kind == TokenKind::Ident
+                    && next.text(input) == "main" =>
+            {
+                return Ok(None);
+            }
+            _ => {}
+        }
+    }
+
+    // Where module-level items end, if code can be appended to that line
+    let mut module_end = None;
+    // Outer attributes and doc-comments that belong to whatever comes next
+    let mut pending_start = None;
+    let mut statement_start = None;
+    let mut i = 0;
+    while let Some(token) = tokens.get(i) {
+        match token.kind {
+            TokenKind::Shebang
+            | TokenKind::LineComment {
+                doc: Some(DocStyle::Inner),
+            }
+            | TokenKind::BlockComment {
+                doc: Some(DocStyle::Inner),
+                ..
+            } => {
+                module_end = None;
+            }
+            TokenKind::LineComment { doc: Some(_) }
+            | TokenKind::BlockComment { doc: Some(_), .. } => {
+                pending_start.get_or_insert(token.span.start);
+            }
+            TokenKind::Punct('#') => {
+                let inner = tokens.get(i + 1).map(|t| t.kind) == Some(TokenKind::Punct('!'));
+                let close = attribute_end(&tokens, i)?;
+                if inner {
+                    module_end = Some(tokens[close].span.end);
+                } else {
+                    pending_start.get_or_insert(token.span.start);
+                }
+                i = close;
+            }
+            _ => match module_item(input, &tokens, i)? {
+                Some((_, end)) => {
+                    module_end = Some(tokens[end].span.end);
+                    pending_start = None;
+                    i = end;
+                }
+                None => {
+                    statement_start = Some(i);
+                    break;
+                }
+            },
+        }
+        i += 1;
+    }
+    let statement_start = match statement_start {
+        Some(start) => start,
+        None => return Ok(None),
+    };
+
+    // Files for `mod foo;` can't be found from within a function, and `extern crate` would only
+    // apply to it, so those among the statements are moved after `fn main`
+    let mut hoisted = Vec::new();
+    let mut depth = 0_usize;
+    let mut attributes_start = None;
+    let mut i = statement_start;
+    while let Some(token) = tokens.get(i) {
+        match token.kind {
+            TokenKind::LineComment { doc: Some(_) }
+            | TokenKind::BlockComment { doc: Some(_), .. }
+                if depth == 0 =>
+            {
+                attributes_start.get_or_insert(token.span.start);
+            }
+            TokenKind::Punct('#') if depth == 0 => {
+                attributes_start.get_or_insert(token.span.start);
+                i = attribute_end(&tokens, i)?;
+            }
+            TokenKind::Punct('(' | '[' | '{') => depth += 1,
+            TokenKind::Punct(')' | ']' | '}') => depth = depth.saturating_sub(1),
+            _ if depth == 0 => match module_item(input, &tokens, i)? {
+                Some(("mod", end)) if tokens[end].kind == TokenKind::Punct(';') => {
+                    hoisted
+                        .push(attributes_start.unwrap_or(token.span.start)..tokens[end].span.end);
+                    i = end;
+                }
+                Some(("extern crate", end)) => {
+                    hoisted
+                        .push(attributes_start.unwrap_or(token.span.start)..tokens[end].span.end);
+                    i = end;
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        if !matches!(
+            token.kind,
+            TokenKind::Punct('#')
+                | TokenKind::LineComment { doc: Some(_) }
+                | TokenKind::BlockComment { doc: Some(_), .. }
+        ) {
+            attributes_start = None;
+        }
+        i += 1;
+    }
+    let mut body = input.to_owned();
+    for span in &hoisted {
+        body = blank(&body, span.clone());
+    }
+
+    let statement_start = tokens[statement_start].span.start;
+    let main_start = module_end.or(pending_start).unwrap_or(statement_start);
+    let mut source = String::with_capacity(input.len() + 32);
+    source.push_str(&body[..main_start]);
+    source.push_str("fn main() {");
+    source.push_str(&body[main_start..]);
+    if !source.ends_with('\n') {
+        source.push('\n');
+    }
+    source.push_str("}\n");
+    for span in hoisted {
+        source.push_str(&input[span]);
+        source.push('\n');
+    }
+    Ok(Some(source))
+}
+
+/// Index of the `]` closing the attribute starting with the `#` at `start`
+fn attribute_end(tokens: &[Token], start: usize) -> CargoResult<usize> {
+    let mut open = start + 1;
+    if tokens.get(open).map(|t| t.kind) == Some(TokenKind::Punct('!')) {
+        open += 1;
+    }
+    if tokens.get(open).map(|t| t.kind) != Some(TokenKind::Punct('[')) {
+        anyhow::bail!("expected `[` after `#`");
+    }
+    let (group, _) = take_group(&mut tokens[open + 1..].iter().cloned(), '[', ']')
+        .ok_or_else(|| anyhow::format_err!("unclosed attribute"))?;
+    Ok(open + 1 + group.len())
+}
+
+/// The kind of `use`, `mod`, or `extern crate` item starting at `start`, with any visibility, and
+/// the index of its last token
+fn module_item(
+    input: &str,
+    tokens: &[Token],
+    start: usize,
+) -> CargoResult<Option<(&'static str, usize)>> {
+    let text = |i: usize| tokens.get(i).map(|t| t.text(input));
+    let mut i = start;
+    if text(i) == Some("pub") {
+        i += 1;
+        if tokens.get(i).map(|t| t.kind) == Some(TokenKind::Punct('(')) {
+            match take_group(&mut tokens[i + 1..].iter().cloned(), '(', ')') {
+                Some((group, _)) => i += group.len() + 2,
+                None => return Ok(None),
+            }
+        }
+    }
+    let kind = match text(i) {
+        Some("use") => "use",
+        Some("mod") => "mod",
+        Some("extern") if text(i + 1) == Some("crate") => "extern crate",
+        _ => return Ok(None),
+    };
+
+    let mut depth = 0_usize;
+    for (i, token) in tokens.iter().enumerate().skip(i) {
+        match token.kind {
+            TokenKind::Punct('(' | '[' | '{') => depth += 1,
+            TokenKind::Punct(')' | ']') => depth = depth.saturating_sub(1),
+            TokenKind::Punct('}') => {
+                depth = depth.saturating_sub(1);
+                // Like `mod inner { ... }`, unlike `use std::{io, fs};`
+                if depth == 0 && kind == "mod" {
+                    return Ok(Some((kind, i)));
+                }
+            }
+            TokenKind::Punct(';') if depth == 0 => return Ok(Some((kind, i))),
+            _ => {}
+        }
+    }
+    anyhow::bail!("expected `;` after `{kind}`")
+}
+
+/// Locates a "code block manifest" in Rust source.
+///
+/// This is the first run of doc-comments before any item, skipping over inner attributes and
+/// regular comments.  Consecutive line doc-comments cannot have any blank lines between them.
+fn extract_comment(input: &str) -> CargoResult<Option<(String, SourceMap)>> {
+    let mut comments = prelude(input)?
+        .into_iter()
+        .filter_map(|item| match item {
+            PreludeItem::DocComment(token) => Some(token),
+            PreludeItem::Attribute { .. } => None,
+        })
+        .peekable();
+    let first = match comments.next() {
+        Some(first) => first,
+        None => return Ok(None),
+    };
+
+    let lines = if let TokenKind::BlockComment { .. } = first.kind {
+        let text = first.text(input);
+        let text = &text[3..text.len() - 2];
+        let mut lines = text.split('\n').map(|l| l.strip_suffix('\r').unwrap_or(l));
+        let first_line = lines.next().unwrap_or_default().trim_start();
+        let mut lines = lines.collect::<Vec<_>>();
+
+        // Strip a ` * ` margin, if every line has one
+        let re_margin = regex::Regex::new(r"^[ \t]*\*( |\t|$)").unwrap();
+        let has_margin = lines
+            .iter()
+            .filter(|l| !l.trim().is_empty())
+            .all(|l| re_margin.is_match(l))
+            && lines.iter().any(|l| !l.trim().is_empty());
+        if has_margin {
+            for line in &mut lines {
+                if let Some(m) = re_margin.find(line) {
+                    *line = &line[m.end()..];
+                }
+            }
+        }
+
+        let mut lines = unindent(lines);
+        lines.insert(0, first_line);
+        lines
+    } else {
+        let mut end = first.span.end;
+        let mut tokens = vec![first.clone()];
+        while let Some(next) = comments.peek() {
+            let between = &input[end..next.span.start];
+            let is_adjacent = between.trim().is_empty() && between.matches('\n').count() == 1;
+            if next.kind != first.kind || !is_adjacent {
+                break;
+            }
+            end = next.span.end;
+            tokens.push(comments.next().unwrap());
+        }
+        let lines = tokens
+            .iter()
+            .map(|t| {
+                let line = &t.text(input)[3..];
+                line.strip_suffix('\r').unwrap_or(line)
+            })
+            .collect::<Vec<_>>();
+        unindent(lines)
+    };
+
+    let mut comment = String::new();
+    let mut map = SourceMap::default();
+    for line in lines {
+        map.push(comment.len(), offset_of(input, line));
+        comment.push_str(line);
+        comment.push('\n');
+    }
+    Ok(Some((comment, map)))
+}
+
+/// Removes the leading whitespace common to all non-blank lines
+///
+/// Tabs are treated like any other whitespace character, so indentation only needs to be
+/// consistent, not space-only.
+fn unindent(lines: Vec<&str>) -> Vec<&str> {
+    let indent = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| &l[..l.len() - l.trim_start().len()])
+        .reduce(|common, indent| {
+            let len = common
+                .char_indices()
+                .zip(indent.chars())
+                .find(|((_, a), b)| a != b)
+                .map(|((i, _), _)| i)
+                .unwrap_or_else(|| common.len().min(indent.len()));
+            &common[..len]
+        })
+        .unwrap_or_default();
+    lines
+        .into_iter()
+        .map(|l| {
+            if l.trim().is_empty() {
+                &l[..0]
+            } else {
+                l.strip_prefix(indent).unwrap_or_else(|| l.trim_start())
+            }
+        })
+        .collect()
+}
+
+/// Extracts the first `Cargo` fenced code block from a chunk of Markdown.
+fn extract_manifest(comment: &str) -> CargoResult<Option<(String, SourceMap)>> {
+    use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
+
+    // To match librustdoc/html/markdown.rs, opts.
+    let exts = Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES;
+
+    let md = Parser::new_ext(comment, exts).into_offset_iter();
+
+    let mut inside = false;
+    let mut output: Option<(String, SourceMap)> = None;
+
+    for (item, range) in md {
+        match item {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info)))
+                if info.to_lowercase() == "cargo" =>
+            {
+                if output.is_some() {
+                    anyhow::bail!("multiple `cargo` manifests present")
+                }
+                // Locate the content even when the block is empty
+                let content = comment[range.clone()]
+                    .find('\n')
+                    .map(|i| range.start + i + 1)
+                    .unwrap_or(range.end);
+                output = Some((String::new(), SourceMap::contiguous(content)));
+                inside = true;
+            }
+            Event::Text(ref text) if inside => {
+                let (s, map) = output.get_or_insert_with(Default::default);
+                if s.is_empty() {
+                    *map = SourceMap::default();
+                }
+                map.push(s.len(), range.start);
+                s.push_str(text);
+            }
+            Event::End(Tag::CodeBlock(_)) if inside => {
+                inside = false;
+            }
+            _ => (),
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test_expand {
+    use super::*;
+
+    macro_rules! si {
+        ($i:expr) => {
+            RawScript::parse($i, std::path::Path::new("/home/me/test.rs"))
+                .unwrap_or_else(|err| panic!("{}", err))
+                .expand_manifest(&cargo::util::Config::default().unwrap())
+                .unwrap_or_else(|err| panic!("{}", err))
+        };
+    }
 
     #[test]
     fn test_default() {
         snapbox::assert_eq(
             r#"[[bin]]
-name = "test_a472c7a31645d310613df407eab80844346938a3b8fe4f392cae059cb181aa85"
+name = "test_86e4e6febe6fea0087c55e6fbffde458665f57f20387644dff1cfeb0a1326bd2"
 path = "/home/me/test.rs"
 
 [package]
@@ -440,11 +1769,61 @@ strip = true
         );
     }
 
+    #[test]
+    fn test_lib() {
+        snapbox::assert_eq(
+            r#"[lib]
+path = "/home/me/test.rs"
+
+[package]
+edition = "2021"
+name = "test"
+publish = false
+version = "0.0.0"
+
+[profile.release]
+strip = true
+
+[workspace]
+"#,
+            si!(r#"pub fn add(left: usize, right: usize) -> usize {
+    left + right
+}
+"#),
+        );
+    }
+
+    #[test]
+    fn test_explicit_lib() {
+        snapbox::assert_eq(
+            r#"[lib]
+path = "/home/me/test.rs"
+
+[package]
+edition = "2021"
+name = "test"
+publish = false
+version = "0.0.0"
+
+[profile.release]
+strip = true
+
+[workspace]
+"#,
+            si!(r#"//! ```cargo
+//! [lib]
+//! ```
+
+pub fn main() {}
+"#),
+        );
+    }
+
     #[test]
     fn test_dependencies() {
         snapbox::assert_eq(
             r#"[[bin]]
-name = "test_3a1fa07700654ea2e893f70bb422efa7884eb1021ccacabc5466efe545da8a0b"
+name = "test_86e4e6febe6fea0087c55e6fbffde458665f57f20387644dff1cfeb0a1326bd2"
 path = "/home/me/test.rs"
 
 [dependencies]
@@ -470,163 +1849,592 @@ fn main() {}
 "#),
         );
     }
-}
-
-#[cfg(test)]
-mod test_comment {
-    use super::*;
-
-    macro_rules! ec {
-        ($s:expr) => {
-            extract_comment($s).unwrap_or_else(|err| panic!("{}", err))
-        };
-    }
 
     #[test]
-    fn test_no_comment() {
-        snapbox::assert_eq(
-            "no doc-comment found",
-            extract_comment(
-                r#"
-fn main () {
-}
-"#,
-            )
-            .unwrap_err()
-            .to_string(),
-        );
-    }
+    fn test_profile() {
+        snapbox::assert_matches(
+            r#"[[bin]]
+name = "test_[..]"
+path = "/home/me/test.rs"
 
-    #[test]
-    fn test_no_comment_she_bang() {
-        snapbox::assert_eq(
-            "no doc-comment found",
-            extract_comment(
-                r#"#!/usr/bin/env cargo-eval
+[package]
+edition = "2021"
+name = "test"
+publish = false
+version = "0.0.0"
 
-fn main () {
-}
-"#,
-            )
-            .unwrap_err()
-            .to_string(),
-        );
-    }
+[profile.fast]
+inherits = "release"
 
-    #[test]
-    fn test_comment() {
-        snapbox::assert_eq(
-            r#"Here is a manifest:
+[profile.release]
+strip = true
 
-```cargo
-[dependencies]
-time = "*"
-```
+[workspace]
 "#,
-            ec!(r#"//! Here is a manifest:
-//!
+            si!(r#"
 //! ```cargo
-//! [dependencies]
-//! time = "*"
+//! [profile.fast]
+//! inherits = "release"
 //! ```
 fn main() {}
 "#),
         );
     }
 
+    macro_rules! si_err {
+        ($i:expr) => {
+            RawScript::parse($i, std::path::Path::new("/home/me/test.rs"))
+                .unwrap_or_else(|err| panic!("{}", err))
+                .expand_manifest(&cargo::util::Config::default().unwrap())
+                .unwrap_err()
+                .to_string()
+        };
+    }
+
     #[test]
-    fn test_comment_shebang() {
+    fn test_invalid_toml() {
         snapbox::assert_eq(
-            r#"Here is a manifest:
-
-```cargo
-[dependencies]
-time = "*"
-```
-"#,
-            ec!(r#"#!/usr/bin/env cargo-eval
+            r#"expected `.`, `=`
+ --> /home/me/test.rs:5:10
+  |
+5 | //! time "0.1.25"
+  |          ^
+  |"#,
+            si_err!(
+                r#"#!/usr/bin/env cargo-eval
 
-//! Here is a manifest:
-//!
 //! ```cargo
 //! [dependencies]
-//! time = "*"
+//! time "0.1.25"
 //! ```
 fn main() {}
-"#),
+"#
+            ),
         );
     }
 
     #[test]
-    fn test_multiline_comment() {
+    fn test_invalid_package() {
+        snapbox::assert_eq(
+            r#"`package` must be a table
+ --> /home/me/test.rs:3:4
+  |
+3 |  * package = 5
+  |    ^^^^^^^
+  |"#,
+            si_err!(
+                r#"/*!
+ * ```cargo
+ * package = 5
+ * ```
+ */
+fn main() {}
+"#
+            ),
+        );
+    }
+
+    #[test]
+    fn test_lib_not_empty() {
+        snapbox::assert_eq(
+            r#"`lib` must be empty in embedded manifests
+ --> /home/me/test.rs:3:2
+  |
+3 | [lib]
+  |  ^^^
+  |"#,
+            si_err!(
+                r#"---cargo
+[package]
+[lib]
+name = "other"
+---
+fn main() {}
+"#
+            ),
+        );
+    }
+
+    #[test]
+    fn test_package_workspace_not_true() {
+        snapbox::assert_eq(
+            r#"`package.workspace` must be `true` in embedded manifests
+ --> /home/me/test.rs:3:1
+  |
+3 | workspace = "../"
+  | ^^^^^^^^^
+  |"#,
+            si_err!(
+                r##"#![cargo(manifest = r#"
+[package]
+workspace = "../"
+"#)]
+fn main() {}
+"##
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_frontmatter {
+    use super::*;
+
+    macro_rules! ef {
+        ($s:expr) => {
+            extract_frontmatter($s)
+                .unwrap_or_else(|err| panic!("{}", err))
+                .map(|((manifest, _), source)| (manifest, source))
+                .unwrap()
+        };
+    }
+
+    #[test]
+    fn test_no_frontmatter() {
+        assert_eq!(
+            extract_frontmatter(
+                r#"//! ```cargo
+//! [dependencies]
+//! time = "*"
+//! ```
+fn main() {}
+"#
+            )
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_frontmatter() {
+        let input = r#"---cargo
+[dependencies]
+time = "*"
+---
+fn main() {}
+"#;
+        let (manifest, source) = ef!(input);
+        snapbox::assert_eq(
+            r#"[dependencies]
+time = "*"
+"#,
+            manifest,
+        );
+        assert_eq!(input.len(), source.len());
         snapbox::assert_eq(
             r#"
-Here is a manifest:
 
-```cargo
+
+
+fn main() {}"#,
+            source
+                .lines()
+                .map(str::trim_end)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        );
+    }
+
+    #[test]
+    fn test_frontmatter_shebang() {
+        let input = r#"#!/usr/bin/env cargo-eval
+
+---cargo
 [dependencies]
 time = "*"
-```
+---
 
+fn main() {}
+"#;
+        let (manifest, source) = ef!(input);
+        snapbox::assert_eq(
+            r#"[dependencies]
+time = "*"
 "#,
-            ec!(r#"/*!
-Here is a manifest:
+            manifest,
+        );
+        assert_eq!(input.len(), source.len());
+        snapbox::assert_eq(
+            r#"#!/usr/bin/env cargo-eval
 
-```cargo
+
+
+
+
+
+fn main() {}"#,
+            source
+                .lines()
+                .map(str::trim_end)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        );
+    }
+
+    #[test]
+    fn test_frontmatter_longer_fence() {
+        let (manifest, _source) = ef!(r#"-----
+[package]
+description = """
+---
+"""
+-----
+fn main() {}
+"#);
+        snapbox::assert_eq(
+            r#"[package]
+description = """
+---
+"""
+"#,
+            manifest,
+        );
+    }
+
+    #[test]
+    fn test_unclosed_frontmatter() {
+        snapbox::assert_eq(
+            "unclosed frontmatter; expected `---`",
+            extract_frontmatter(
+                r#"---cargo
 [dependencies]
-time = "*"
-```
-*/
+fn main() {}
+"#,
+            )
+            .unwrap_err()
+            .to_string(),
+        );
+    }
 
-fn main() {
+    #[test]
+    fn test_unsupported_infostring() {
+        snapbox::assert_eq(
+            "unsupported frontmatter infostring `toml`; expected `cargo`",
+            extract_frontmatter(
+                r#"---toml
+---
+fn main() {}
+"#,
+            )
+            .unwrap_err()
+            .to_string(),
+        );
+    }
+
+    #[test]
+    fn test_frontmatter_and_comment() {
+        snapbox::assert_eq(
+            "multiple `cargo` manifests present (frontmatter, doc-comment); only one is allowed",
+            RawScript::parse(
+                r#"---cargo
+---
+//! ```cargo
+//! ```
+fn main() {}
+"#,
+                std::path::Path::new("/home/me/test.rs"),
+            )
+            .err()
+            .unwrap()
+            .to_string(),
+        );
+    }
 }
-"#),
+
+#[cfg(test)]
+mod test_attribute {
+    use super::*;
+
+    macro_rules! ea {
+        ($s:expr) => {
+            extract_attribute($s)
+                .unwrap_or_else(|err| panic!("{}", err))
+                .map(|((manifest, _), source)| (manifest, source))
+                .unwrap()
+        };
+    }
+
+    #[test]
+    fn test_no_attribute() {
+        assert_eq!(
+            extract_attribute(
+                r#"#![allow(dead_code)]
+fn main() {}
+"#
+            )
+            .unwrap(),
+            None
         );
     }
 
     #[test]
-    fn test_multiline_comment_shebang() {
+    fn test_attribute() {
+        let input = r##"#!/usr/bin/env cargo-eval
+#![cargo(manifest = r#"
+[dependencies]
+time = "*"
+"#)]
+#![allow(dead_code)]
+fn main() {}
+"##;
+        let (manifest, source) = ea!(input);
         snapbox::assert_eq(
             r#"
-Here is a manifest:
-
-```cargo
 [dependencies]
 time = "*"
-```
+"#,
+            manifest,
+        );
+        assert_eq!(input.len(), source.len());
+        snapbox::assert_eq(
+            r#"#!/usr/bin/env cargo-eval
+
+
+
+
+#![allow(dead_code)]
+fn main() {}"#,
+            source
+                .lines()
+                .map(str::trim_end)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        );
+    }
+
+    #[test]
+    fn test_attribute_hashes() {
+        let (manifest, _source) = ea!(r###"#![cargo(manifest = r##"
+[package]
+description = 'has "# in it'
+"##)]
+fn main() {}
+"###);
+        snapbox::assert_eq(
+            r##"
+[package]
+description = 'has "# in it'
+"##,
+            manifest,
+        );
+    }
 
+    #[test]
+    fn test_attribute_not_raw() {
+        snapbox::assert_eq(
+            r##"expected `#![cargo(manifest = r#"..."#)]`"##,
+            extract_attribute(
+                r#"#![cargo(manifest = "[dependencies]")]
+fn main() {}
 "#,
-            ec!(r#"#!/usr/bin/env cargo-eval
+            )
+            .unwrap_err()
+            .to_string(),
+        );
+    }
 
-/*!
-Here is a manifest:
+    #[test]
+    fn test_attribute_and_comment() {
+        snapbox::assert_eq(
+            "multiple `cargo` manifests present (attribute, doc-comment); only one is allowed",
+            RawScript::parse(
+                r##"#![cargo(manifest = r#""#)]
+//! ```cargo
+//! ```
+fn main() {}
+"##,
+                std::path::Path::new("/home/me/test.rs"),
+            )
+            .err()
+            .unwrap()
+            .to_string(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_comment {
+    use super::*;
+
+    macro_rules! ec {
+        ($s:expr) => {
+            extract_comment($s)
+                .unwrap_or_else(|err| panic!("{}", err))
+                .unwrap()
+                .0
+        };
+    }
+
+    #[test]
+    fn test_no_comment() {
+        assert_eq!(
+            extract_comment(
+                r#"
+fn main () {
+}
+"#,
+            )
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_no_comment_she_bang() {
+        assert_eq!(
+            extract_comment(
+                r#"#!/usr/bin/env cargo-eval
+
+fn main () {
+}
+"#,
+            )
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_comment() {
+        snapbox::assert_eq(
+            r#"Here is a manifest:
 
 ```cargo
 [dependencies]
 time = "*"
 ```
-*/
-
-fn main() {
-}
+"#,
+            ec!(r#"//! Here is a manifest:
+//!
+//! ```cargo
+//! [dependencies]
+//! time = "*"
+//! ```
+fn main() {}
 "#),
         );
     }
 
     #[test]
-    fn test_multiline_block_comment() {
+    fn test_comment_shebang() {
         snapbox::assert_eq(
-            r#"
-Here is a manifest:
+            r#"Here is a manifest:
 
 ```cargo
 [dependencies]
 time = "*"
 ```
-
 "#,
-            ec!(r#"/*!
- * Here is a manifest:
+            ec!(r#"#!/usr/bin/env cargo-eval
+
+//! Here is a manifest:
+//!
+//! ```cargo
+//! [dependencies]
+//! time = "*"
+//! ```
+fn main() {}
+"#),
+        );
+    }
+
+    #[test]
+    fn test_multiline_comment() {
+        snapbox::assert_eq(
+            r#"
+Here is a manifest:
+
+```cargo
+[dependencies]
+time = "*"
+```
+
+"#,
+            ec!(r#"/*!
+Here is a manifest:
+
+```cargo
+[dependencies]
+time = "*"
+```
+*/
+
+fn main() {
+}
+"#),
+        );
+    }
+
+    #[test]
+    fn test_multiline_comment_shebang() {
+        snapbox::assert_eq(
+            r#"
+Here is a manifest:
+
+```cargo
+[dependencies]
+time = "*"
+```
+
+"#,
+            ec!(r#"#!/usr/bin/env cargo-eval
+
+/*!
+Here is a manifest:
+
+```cargo
+[dependencies]
+time = "*"
+```
+*/
+
+fn main() {
+}
+"#),
+        );
+    }
+
+    #[test]
+    fn test_multiline_block_comment() {
+        snapbox::assert_eq(
+            r#"
+Here is a manifest:
+
+```cargo
+[dependencies]
+time = "*"
+```
+
+"#,
+            ec!(r#"/*!
+ * Here is a manifest:
+ *
+ * ```cargo
+ * [dependencies]
+ * time = "*"
+ * ```
+ */
+fn main() {}
+"#),
+        );
+    }
+
+    #[test]
+    fn test_multiline_block_comment_shebang() {
+        snapbox::assert_eq(
+            r#"
+Here is a manifest:
+
+```cargo
+[dependencies]
+time = "*"
+```
+
+"#,
+            ec!(r#"#!/usr/bin/env cargo-eval
+
+/*!
+ * Here is a manifest:
  *
  * ```cargo
  * [dependencies]
@@ -634,211 +2442,1048 @@ time = "*"
  * ```
  */
 fn main() {}
-"#),
+"#),
+        );
+    }
+
+    #[test]
+    fn test_comment_after_inner_attributes() {
+        snapbox::assert_eq(
+            r#"```cargo
+[dependencies]
+time = "*"
+```
+"#,
+            ec!(r#"#!/usr/bin/env cargo-eval
+#![allow(dead_code)]
+#![cfg_attr(test, allow(
+    unused_imports,
+))]
+//! ```cargo
+//! [dependencies]
+//! time = "*"
+//! ```
+fn main() {}
+"#),
+        );
+    }
+
+    #[test]
+    fn test_comment_after_regular_comments() {
+        snapbox::assert_eq(
+            r#"```cargo
+[dependencies]
+time = "*"
+```
+"#,
+            ec!(r#"// Copyright me
+/* Licensed as
+ * /* nested */
+ * whatever */
+
+//! ```cargo
+//! [dependencies]
+//! time = "*"
+//! ```
+fn main() {}
+"#),
+        );
+    }
+
+    #[test]
+    fn test_comment_crlf() {
+        snapbox::assert_eq(
+            "```cargo\n[dependencies]\ntime = \"*\"\n```\n",
+            ec!("#!/usr/bin/env cargo-eval\r\n//! ```cargo\r\n//! [dependencies]\r\n//! time = \"*\"\r\n//! ```\r\nfn main() {}\r\n"),
+        );
+    }
+
+    #[test]
+    fn test_comment_tabs() {
+        snapbox::assert_eq(
+            "```cargo\n[dependencies]\n\ttime = \"*\"\n```\n",
+            ec!(
+                "//!\t```cargo\n//!\t[dependencies]\n//!\t\ttime = \"*\"\n//!\t```\nfn main() {}\n"
+            ),
+        );
+    }
+
+    #[test]
+    fn test_comment_blank_line_ends_comment() {
+        snapbox::assert_eq(
+            r#"```cargo
+[dependencies]
+```
+"#,
+            ec!(r#"//! ```cargo
+//! [dependencies]
+//! ```
+
+//! time = "*"
+fn main() {}
+"#),
+        );
+    }
+
+    #[test]
+    fn test_multiline_comment_nested() {
+        snapbox::assert_eq(
+            r#"
+```cargo
+[dependencies] /* not the end */
+time = "*"
+```
+
+"#,
+            ec!(r#"/*!
+```cargo
+[dependencies] /* not the end */
+time = "*"
+```
+*/
+fn main() {}
+"#),
+        );
+    }
+
+    #[test]
+    fn test_multiline_block_comment_odd_margin() {
+        snapbox::assert_eq(
+            r#"
+```cargo
+[dependencies]
+time = "*"
+```
+
+"#,
+            ec!(r#"/*!
+   *  ```cargo
+   *  [dependencies]
+   *  time = "*"
+   *  ```
+   */
+fn main() {}
+"#),
+        );
+    }
+
+    #[test]
+    fn test_multiline_block_comment_tabs() {
+        snapbox::assert_eq(
+            "\n```cargo\n[dependencies]\n```\n\n",
+            ec!("/*!\n\t```cargo\n\t[dependencies]\n\t```\n*/\nfn main() {}\n"),
+        );
+    }
+
+    #[test]
+    fn test_unterminated_comment() {
+        snapbox::assert_eq(
+            "unterminated block comment",
+            extract_comment(
+                r#"/*!
+```cargo
+```
+fn main() {}
+"#,
+            )
+            .unwrap_err()
+            .to_string(),
+        );
+    }
+}
+
+/// Paths to dependencies in a manifest
+const DEPENDENCY_PATHS: &[&[&str]] = &[
+    &["build-dependencies", "*", "path"],
+    &["dependencies", "*", "path"],
+    &["dev-dependencies", "*", "path"],
+    &["target", "*", "dependencies", "*", "path"],
+];
+
+/// Given a Cargo manifest, attempts to rewrite relative file paths to absolute ones, allowing the manifest to be relocated.
+fn remap_paths(
+    mani: toml::Table,
+    package_root: &std::path::Path,
+) -> anyhow::Result<toml::value::Table> {
+    // Values that need to be rewritten:
+    let paths = DEPENDENCY_PATHS.iter().copied().chain([
+        &["package", "build"][..],
+        &["patch", "*", "*", "path"],
+        &["workspace", "dependencies", "*", "path"],
+        &["workspace", "package", "license-file"],
+        &["workspace", "package", "readme"],
+    ]);
+
+    let mut mani = toml::Value::Table(mani);
+
+    for path in paths {
+        iterate_toml_mut_path(&mut mani, path, &mut |v| {
+            if let toml::Value::String(s) = v {
+                if std::path::Path::new(s).is_relative() {
+                    let p = package_root.join(&*s);
+                    if let Some(p) = p.to_str() {
+                        *s = p.into()
+                    }
+                }
+            }
+            Ok(())
+        })?
+    }
+
+    match mani {
+        toml::Value::Table(mani) => Ok(mani),
+        _ => unreachable!(),
+    }
+}
+
+/// Gives the script's manifest what it would get as a member of the workspace at `root`, returning
+/// the `[workspace]` to inherit from
+///
+/// The script's own `[patch]` and `[profile]` settings take precedence over the workspace's.
+fn inherit_workspace(
+    mani: &mut toml::Table,
+    root: &std::path::Path,
+) -> anyhow::Result<toml::Table> {
+    let root_manifest_path = root.join("Cargo.toml");
+    let root_manifest = cargo_util::paths::read(&root_manifest_path)?
+        .parse::<toml::Table>()
+        .with_context(|| format!("failed to parse {}", root_manifest_path.display()))?;
+    let root_manifest = remap_paths(root_manifest, root)?;
+
+    let mut workspace = toml::Table::new();
+    if let Some(root_workspace) = root_manifest.get("workspace").and_then(|w| w.as_table()) {
+        for key in ["package", "dependencies"] {
+            if let Some(value) = root_workspace.get(key) {
+                workspace.insert(key.to_owned(), value.clone());
+            }
+        }
+    }
+
+    for key in ["patch", "profile"] {
+        let root_tables = match root_manifest.get(key).and_then(|t| t.as_table()) {
+            Some(root_tables) => root_tables,
+            None => continue,
+        };
+        let tables = mani
+            .entry(key.to_owned())
+            .or_insert_with(|| toml::Table::new().into())
+            .as_table_mut()
+            .ok_or_else(|| anyhow::format_err!("`{key}` must be a table"))?;
+        for (name, root_table) in root_tables {
+            match (tables.get_mut(name), root_table) {
+                (Some(toml::Value::Table(table)), toml::Value::Table(root_table)) => {
+                    for (field, value) in root_table {
+                        table.entry(field.clone()).or_insert_with(|| value.clone());
+                    }
+                }
+                (Some(_), _) => {}
+                (None, _) => {
+                    tables.insert(name.clone(), root_table.clone());
+                }
+            }
+        }
+    }
+
+    Ok(workspace)
+}
+
+/// Points absolute `path` dependencies on scripts, rather than package directories, at the library
+/// packages generated for them
+fn remap_script_dependencies(
+    mani: toml::Table,
+    target_dir: &std::path::Path,
+) -> anyhow::Result<(toml::Table, Vec<RawScript>)> {
+    let mut scripts = Vec::new();
+    let mut mani = toml::Value::Table(mani);
+
+    for path in DEPENDENCY_PATHS {
+        iterate_toml_mut_path(&mut mani, path, &mut |v| {
+            if let toml::Value::String(s) = v {
+                let path = std::path::Path::new(s.as_str());
+                if path.extension() == Some("rs".as_ref()) || path.is_file() {
+                    let script = RawScript::parse_from(path)?;
+                    if !script.is_lib() {
+                        anyhow::bail!(
+                            "`{}` is not a library, so can't be depended on
+
+help: remove `fn main` and top-level statements, or add an empty `[lib]` to its manifest",
+                            path.display()
+                        );
+                    }
+                    let package_root = script.workspace_root(target_dir)?;
+                    *s = package_root
+                        .to_str()
+                        .ok_or_else(|| anyhow::format_err!("path is not valid UTF-8"))?
+                        .into();
+                    scripts.push(script);
+                }
+            }
+            Ok(())
+        })?
+    }
+
+    match mani {
+        toml::Value::Table(mani) => Ok((mani, scripts)),
+        _ => unreachable!(),
+    }
+}
+
+/// Iterates over the specified TOML values via a path specification.
+fn iterate_toml_mut_path<F>(
+    base: &mut toml::Value,
+    path: &[&str],
+    on_each: &mut F,
+) -> anyhow::Result<()>
+where
+    F: FnMut(&mut toml::Value) -> anyhow::Result<()>,
+{
+    if path.is_empty() {
+        return on_each(base);
+    }
+
+    let cur = path[0];
+    let tail = &path[1..];
+
+    if cur == "*" {
+        if let toml::Value::Table(tab) = base {
+            for (_, v) in tab {
+                iterate_toml_mut_path(v, tail, on_each)?;
+            }
+        }
+    } else if let toml::Value::Table(tab) = base {
+        if let Some(v) = tab.get_mut(cur) {
+            iterate_toml_mut_path(v, tail, on_each)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_manifest {
+    use super::*;
+
+    macro_rules! smm {
+        ($c:expr) => {
+            extract_manifest($c).map(|m| m.map(|(manifest, _)| manifest))
+        };
+    }
+
+    #[test]
+    fn test_no_code_fence() {
+        assert_eq!(
+            smm!(
+                r#"There is no manifest in this comment.
+"#
+            )
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_no_cargo_code_fence() {
+        assert_eq!(
+            smm!(
+                r#"There is no manifest in this comment.
+
+```
+This is not a manifest.
+```
+
+```rust
+println!("Nor is this.");
+```
+
+    Or this.
+"#
+            )
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_cargo_code_fence() {
+        assert_eq!(
+            smm!(
+                r#"This is a manifest:
+
+```cargo
+dependencies = { time = "*" }
+```
+"#
+            )
+            .unwrap(),
+            Some(
+                r#"dependencies = { time = "*" }
+"#
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_mixed_code_fence() {
+        assert_eq!(
+            smm!(
+                r#"This is *not* a manifest:
+
+```
+He's lying, I'm *totally* a manifest!
+```
+
+This *is*:
+
+```cargo
+dependencies = { time = "*" }
+```
+"#
+            )
+            .unwrap(),
+            Some(
+                r#"dependencies = { time = "*" }
+"#
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_two_cargo_code_fence() {
+        assert!(smm!(
+            r#"This is a manifest:
+
+```cargo
+dependencies = { time = "*" }
+```
+
+So is this, but it doesn't count:
+
+```cargo
+dependencies = { explode = true }
+```
+"#
+        )
+        .is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_replace_manifest {
+    use super::*;
+
+    macro_rules! rm {
+        ($s:expr, $m:expr) => {
+            RawScript::parse($s, std::path::Path::new("script.rs"))
+                .unwrap_or_else(|err| panic!("{}", err))
+                .replace_manifest($m)
+                .unwrap_or_else(|err| panic!("{}", err))
+        };
+    }
+
+    const MANIFEST: &str = r#"[dependencies]
+time = "0.1.25"
+
+[features]
+default = []
+"#;
+
+    #[test]
+    fn test_line_comment() {
+        snapbox::assert_eq(
+            r#"#!/usr/bin/env cargo-eval
+//! Doc
+//!
+//! ```cargo
+//! [dependencies]
+//! time = "0.1.25"
+//!
+//! [features]
+//! default = []
+//! ```
+fn main() {}
+"#,
+            rm!(
+                r#"#!/usr/bin/env cargo-eval
+//! Doc
+//!
+//! ```cargo
+//! [dependencies]
+//! ```
+fn main() {}
+"#,
+                MANIFEST
+            ),
+        );
+    }
+
+    #[test]
+    fn test_outer_line_comment() {
+        snapbox::assert_eq(
+            r#"/// ```cargo
+/// [dependencies]
+/// time = "0.1.25"
+///
+/// [features]
+/// default = []
+/// ```
+fn main() {}
+"#,
+            rm!(
+                r#"/// ```cargo
+/// [dependencies]
+/// ```
+fn main() {}
+"#,
+                MANIFEST
+            ),
+        );
+    }
+
+    #[test]
+    fn test_block_comment_with_margin() {
+        snapbox::assert_eq(
+            r#"/*!
+ * ```cargo
+ * [dependencies]
+ * time = "0.1.25"
+ *
+ * [features]
+ * default = []
+ * ```
+ */
+fn main() {}
+"#,
+            rm!(
+                r#"/*!
+ * ```cargo
+ * [dependencies]
+ * ```
+ */
+fn main() {}
+"#,
+                MANIFEST
+            ),
+        );
+    }
+
+    #[test]
+    fn test_indented_block_comment() {
+        snapbox::assert_eq(
+            r#"/*!
+    ```cargo
+    [dependencies]
+    time = "0.1.25"
+
+    [features]
+    default = []
+    ```
+*/
+fn main() {}
+"#,
+            rm!(
+                r#"/*!
+    ```cargo
+    [dependencies]
+    ```
+*/
+fn main() {}
+"#,
+                MANIFEST
+            ),
+        );
+    }
+
+    #[test]
+    fn test_frontmatter() {
+        snapbox::assert_eq(
+            r#"---
+[dependencies]
+time = "0.1.25"
+
+[features]
+default = []
+---
+fn main() {}
+"#,
+            rm!(
+                r#"---
+[dependencies]
+---
+fn main() {}
+"#,
+                MANIFEST
+            ),
+        );
+    }
+
+    #[test]
+    fn test_attribute() {
+        snapbox::assert_eq(
+            r##"#![cargo(manifest = r#"
+[dependencies]
+time = "0.1.25"
+
+[features]
+default = []
+"#)]
+fn main() {}
+"##,
+            rm!(
+                r##"#![cargo(manifest = r#"
+[dependencies]
+"#)]
+fn main() {}
+"##,
+                &format!("\n{MANIFEST}")
+            ),
+        );
+    }
+
+    #[test]
+    fn test_crlf() {
+        snapbox::assert_eq(
+            "//! ```cargo\r\n//! [dependencies]\r\n//! time = \"0.1.25\"\r\n//!\r\n//! [features]\r\n//! default = []\r\n//! ```\r\nfn main() {}\r\n",
+            rm!(
+                "//! ```cargo\r\n//! [dependencies]\r\n//! ```\r\nfn main() {}\r\n",
+                MANIFEST
+            ),
+        );
+    }
+
+    #[test]
+    fn test_to_empty() {
+        snapbox::assert_eq(
+            r#"//! ```cargo
+//! ```
+fn main() {}
+"#,
+            rm!(
+                r#"//! ```cargo
+//! [dependencies]
+//! ```
+fn main() {}
+"#,
+                ""
+            ),
+        );
+    }
+
+    #[test]
+    fn test_from_empty() {
+        snapbox::assert_eq(
+            r#"/*!
+ * ```cargo
+ * [dependencies]
+ * time = "0.1.25"
+ *
+ * [features]
+ * default = []
+ * ```
+ */
+fn main() {}
+"#,
+            rm!(
+                r#"/*!
+ * ```cargo
+ * ```
+ */
+fn main() {}
+"#,
+                MANIFEST
+            ),
         );
     }
 
     #[test]
-    fn test_multiline_block_comment_shebang() {
+    fn test_no_manifest() {
         snapbox::assert_eq(
-            r#"
-Here is a manifest:
-
-```cargo
-[dependencies]
-time = "*"
-```
-
+            r#"#!/usr/bin/env cargo-eval
+//! ```cargo
+//! [dependencies]
+//! time = "0.1.25"
+//!
+//! [features]
+//! default = []
+//! ```
+fn main() {}
 "#,
-            ec!(r#"#!/usr/bin/env cargo-eval
-
-/*!
- * Here is a manifest:
- *
- * ```cargo
- * [dependencies]
- * time = "*"
- * ```
- */
+            rm!(
+                r#"#!/usr/bin/env cargo-eval
 fn main() {}
-"#),
+"#,
+                MANIFEST
+            ),
         );
     }
 }
 
-/// Given a Cargo manifest, attempts to rewrite relative file paths to absolute ones, allowing the manifest to be relocated.
-fn remap_paths(
-    mani: toml::Table,
-    package_root: &std::path::Path,
-) -> anyhow::Result<toml::value::Table> {
-    // Values that need to be rewritten:
-    let paths: &[&[&str]] = &[
-        &["build-dependencies", "*", "path"],
-        &["dependencies", "*", "path"],
-        &["dev-dependencies", "*", "path"],
-        &["package", "build"],
-        &["target", "*", "dependencies", "*", "path"],
-    ];
+#[cfg(test)]
+mod test_is_library {
+    use super::*;
 
-    let mut mani = toml::Value::Table(mani);
+    #[test]
+    fn test_items() {
+        assert!(is_library(
+            r#"#!/usr/bin/env cargo-eval
+//! Doc
+#![allow(unused)]
+
+use std::collections::{HashMap, HashSet};
+
+/// A point
+#[derive(Debug)]
+pub struct Point {
+    x: i32,
+    y: i32,
+}
 
-    for path in paths {
-        iterate_toml_mut_path(&mut mani, path, &mut |v| {
-            if let toml::Value::String(s) = v {
-                if std::path::Path::new(s).is_relative() {
-                    let p = package_root.join(&*s);
-                    if let Some(p) = p.to_str() {
-                        *s = p.into()
-                    }
-                }
-            }
-            Ok(())
-        })?
+pub const ORIGIN: Point = Point { x: 0, y: 0 };
+
+impl Point {
+    pub fn main(&self) {}
+}
+
+mod inner {
+    fn main() {}
+}
+
+pub(crate) fn distance(a: &Point, b: &Point) -> [i32; 2] {
+    [a.x - b.x, a.y - b.y]
+}
+"#
+        ));
     }
 
-    match mani {
-        toml::Value::Table(mani) => Ok(mani),
-        _ => unreachable!(),
+    #[test]
+    fn test_main() {
+        assert!(!is_library(
+            "use std::io;
+
+fn main() {}
+"
+        ));
+        assert!(!is_library(
+            "#[tokio::main]
+pub async fn main() {}
+"
+        ));
     }
-}
 
-/// Iterates over the specified TOML values via a path specification.
-fn iterate_toml_mut_path<F>(
-    base: &mut toml::Value,
-    path: &[&str],
-    on_each: &mut F,
-) -> anyhow::Result<()>
-where
-    F: FnMut(&mut toml::Value) -> anyhow::Result<()>,
-{
-    if path.is_empty() {
-        return on_each(base);
+    #[test]
+    fn test_statements() {
+        assert!(!is_library(
+            "fn helper() {}
+
+helper();
+"
+        ));
+        assert!(!is_library(
+            "println!(\"Hello\");
+"
+        ));
+        assert!(!is_library(
+            "unsafe {
+    println!(\"Hello\");
+}
+"
+        ));
+        assert!(!is_library(
+            "async move {};
+"
+        ));
     }
 
-    let cur = path[0];
-    let tail = &path[1..];
+    #[test]
+    fn test_qualified_items() {
+        assert!(is_library(
+            "unsafe fn danger() {}
 
-    if cur == "*" {
-        if let toml::Value::Table(tab) = base {
-            for (_, v) in tab {
-                iterate_toml_mut_path(v, tail, on_each)?;
-            }
-        }
-    } else if let toml::Value::Table(tab) = base {
-        if let Some(v) = tab.get_mut(cur) {
-            iterate_toml_mut_path(v, tail, on_each)?;
-        }
+pub async unsafe fn later() {}
+
+unsafe impl Send for Point {}
+
+unsafe extern \"C\" {}
+"
+        ));
     }
 
-    Ok(())
+    #[test]
+    fn test_empty() {
+        assert!(!is_library(""));
+        assert!(!is_library(
+            "#!/usr/bin/env cargo-eval
+// Comment
+"
+        ));
+    }
 }
 
 #[cfg(test)]
-mod test_manifest {
+mod test_implicit_main {
     use super::*;
 
-    macro_rules! smm {
-        ($c:expr) => {
-            extract_manifest($c)
+    macro_rules! im {
+        ($s:expr) => {
+            implicit_main($s).unwrap_or_else(|err| panic!("{}", err))
         };
     }
 
     #[test]
-    fn test_no_code_fence() {
+    fn test_has_main() {
         assert_eq!(
-            smm!(
-                r#"There is no manifest in this comment.
-"#
-            )
-            .unwrap(),
+            im!(r#"use std::io;
+
+fn main() {
+    println!("Hello");
+}
+"#),
+            None
+        );
+        assert_eq!(
+            im!(r#"#[tokio::main]
+async fn main() {}
+"#),
             None
         );
     }
 
     #[test]
-    fn test_no_cargo_code_fence() {
-        assert_eq!(
-            smm!(
-                r#"There is no manifest in this comment.
+    fn test_no_statements() {
+        assert_eq!(im!("use std::io;\n"), None);
+    }
 
-```
-This is not a manifest.
-```
+    #[test]
+    fn test_statements() {
+        snapbox::assert_eq(
+            r#"#!/usr/bin/env cargo-eval
 
-```rust
-println!("Nor is this.");
-```
+fn main() {println!("Hello");
+}
+"#,
+            im!(r#"#!/usr/bin/env cargo-eval
 
-    Or this.
-"#
-            )
+println!("Hello");
+"#)
             .unwrap(),
-            None
         );
     }
 
     #[test]
-    fn test_cargo_code_fence() {
-        assert_eq!(
-            smm!(
-                r#"This is a manifest:
+    fn test_module_items() {
+        snapbox::assert_eq(
+            r#"#![allow(unused)]
+//! Doc
+/// Imports
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+extern crate core;fn main() {
+
+#[allow(unused_variables)]
+let map = HashMap::<u32, u32>::new();
+fn helper() {}
+}
+"#,
+            im!(r#"#![allow(unused)]
+//! Doc
+/// Imports
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+extern crate core;
+
+#[allow(unused_variables)]
+let map = HashMap::<u32, u32>::new();
+fn helper() {}
+"#)
+            .unwrap(),
+        );
+    }
 
-```cargo
-dependencies = { time = "*" }
-```
-"#
-            )
+    #[test]
+    fn test_nested_main() {
+        snapbox::assert_eq(
+            r#"mod inner {
+    fn main() {}
+}fn main() {
+inner::main();
+}
+"#,
+            im!(r#"mod inner {
+    fn main() {}
+}
+inner::main();"#)
             .unwrap(),
-            Some(
-                r#"dependencies = { time = "*" }
-"#
-                .into()
-            )
         );
     }
 
     #[test]
-    fn test_mixed_code_fence() {
-        assert_eq!(
-            smm!(
-                r#"This is *not* a manifest:
+    fn test_modules() {
+        // Moved items are blanked out with spaces
+        let source = im!(r#"mod helper;
+pub(crate) mod inline {}
+
+println!("{}", helper::greeting());
+/// Later
+#[path = "other.rs"]
+mod later;
+extern crate core as other_core;
+"#)
+        .unwrap();
+        snapbox::assert_eq(
+            r#"mod helper;
+pub(crate) mod inline {}fn main() {
 
-```
-He's lying, I'm *totally* a manifest!
-```
+println!("{}", helper::greeting());
 
-This *is*:
 
-```cargo
-dependencies = { time = "*" }
-```
-"#
+
+
+}
+/// Later
+#[path = "other.rs"]
+mod later;
+extern crate core as other_core;
+"#,
+            source
+                .lines()
+                .map(|line| format!("{}\n", line.trim_end()))
+                .collect::<String>(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_absolute_paths {
+    use super::*;
+
+    macro_rules! ap {
+        ($s:expr) => {
+            absolute_paths($s, std::path::Path::new("/home/me/scripts"))
+                .unwrap_or_else(|err| panic!("{}", err))
+        };
+    }
+
+    #[test]
+    fn test_mod() {
+        snapbox::assert_eq(
+            r#"#[path = "/home/me/scripts/helper.rs"] mod helper;
+/// Docs
+#[path = "/home/me/scripts/other.rs"] pub(crate) mod other;
+#[cfg(test)]
+#[path = "/home/me/scripts/tests"] mod tests {
+    mod nested;
+}
+
+fn main() {}
+"#,
+            ap!(r#"mod helper;
+/// Docs
+pub(crate) mod other;
+#[cfg(test)]
+mod tests {
+    mod nested;
+}
+
+fn main() {}
+"#),
+        );
+    }
+
+    #[test]
+    fn test_mod_with_file_modules() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/nested");
+        let expected = r#"#[cfg(all())]
+#[path = "[DIR]"] mod __cargo_eval_greeting { #[allow(unused_imports)] use super::*; #[macro_use] pub mod greeting; } #[cfg(all())] #[allow(unused_imports)] pub(crate) use self::__cargo_eval_greeting::greeting;
+#[path = "[DIR]/missing.rs"] mod missing;
+"#;
+        snapbox::assert_eq(
+            expected.replace("[DIR]", dir.to_str().unwrap()),
+            absolute_paths(
+                "#[cfg(all())]
+pub(crate) mod greeting;
+mod missing;
+",
+                &dir,
             )
             .unwrap(),
-            Some(
-                r#"dependencies = { time = "*" }
-"#
-                .into()
-            )
         );
     }
 
     #[test]
-    fn test_two_cargo_code_fence() {
-        assert!(smm!(
-            r#"This is a manifest:
+    fn test_declares_file_modules() {
+        assert!(declares_file_modules("pub mod name;\n"));
+        assert!(declares_file_modules("mod inline {\n    mod name;\n}\n"));
+        assert!(!declares_file_modules("mod inline {}\nfn name() {}\n"));
+    }
 
-```cargo
-dependencies = { time = "*" }
-```
+    #[test]
+    fn test_path_attribute() {
+        snapbox::assert_eq(
+            r#"#[path = "/home/me/scripts/src/helper.rs"]
+mod helper;
+#[path = "/elsewhere/other.rs"]
+mod other;
+"#,
+            ap!(r#"#[path = "src/helper.rs"]
+mod helper;
+#[path = "/elsewhere/other.rs"]
+mod other;
+"#),
+        );
+    }
 
-So is this, but it doesn't count:
+    #[test]
+    fn test_include() {
+        snapbox::assert_eq(
+            r##"fn main() {
+    let data = include_str!("/home/me/scripts/data.txt");
+    let bytes = include_bytes!("/home/me/scripts/data.bin");
+    include!("/home/me/scripts/generated.rs");
+    let absolute = include_str!("/etc/hosts");
+}
+"##,
+            ap!(r##"fn main() {
+    let data = include_str!("data.txt");
+    let bytes = include_bytes!(r#"data.bin"#);
+    include!("generated.rs");
+    let absolute = include_str!("/etc/hosts");
+}
+"##),
+        );
+    }
+}
 
-```cargo
-dependencies = { explode = true }
-```
-"#
-        )
-        .is_err());
+#[cfg(test)]
+mod test_shebang_features {
+    use super::*;
+
+    macro_rules! sf {
+        ($s:expr) => {
+            RawScript::parse($s, std::path::Path::new("script.rs"))
+                .unwrap_or_else(|err| panic!("{}", err))
+                .shebang_features()
+        };
+    }
+
+    #[test]
+    fn test_no_shebang() {
+        assert_eq!(sf!("fn main() {}\n"), Vec::<String>::new());
+        assert_eq!(
+            sf!("#![cfg(feature = \"foo\")]\nfn main() {}\n"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_features() {
+        assert_eq!(
+            sf!("#!/usr/bin/env -S cargo eval --features foo,bar -Fbaz --features=qux\r\nfn main() {}\n"),
+            ["foo", "bar", "baz", "qux"]
+        );
     }
 }
@@ -0,0 +1,6 @@
+fn main() {
+    let arg0 = std::env::args().next().unwrap();
+    let exe = std::env::current_exe().unwrap();
+    println!("arg0: {arg0}");
+    println!("exe: {}", exe.file_name().unwrap().to_string_lossy());
+}
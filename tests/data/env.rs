@@ -0,0 +1,12 @@
+fn main() {
+    for key in [
+        "CARGO_EVAL_SCRIPT",
+        "CARGO_EVAL_SCRIPT_DIR",
+        "CARGO_EVAL_SCRIPT_HASH",
+        "CARGO_EVAL_PROFILE",
+        "CARGO_EVAL_STDIN",
+    ] {
+        let value = std::env::var(key).unwrap_or_else(|_| "<unset>".to_owned());
+        println!("{key}: {value}");
+    }
+}
@@ -0,0 +1,19 @@
+#!/usr/bin/env -S cargo eval --features shebang
+//! ```cargo
+//! [features]
+//! default = ["dflt"]
+//! dflt = []
+//! cli = []
+//! shebang = []
+//! other = []
+//! ```
+
+fn main() {
+    println!(
+        "dflt: {}, cli: {}, shebang: {}, other: {}",
+        cfg!(feature = "dflt"),
+        cfg!(feature = "cli"),
+        cfg!(feature = "shebang"),
+        cfg!(feature = "other")
+    );
+}
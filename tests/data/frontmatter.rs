@@ -0,0 +1,10 @@
+#!/usr/bin/env cargo-eval
+
+---cargo
+[dependencies]
+path_dep.path = "path_dep"
+---
+
+fn main() {
+    println!("{} line: {}", path_dep::message(), line!());
+}
@@ -0,0 +1,21 @@
+---
+[package]
+edition = "2021"
+---
+
+/// Adds two numbers
+///
+/// ```
+/// assert_eq!(lib_frontmatter::add(1, 2), 3);
+/// ```
+pub fn add(left: usize, right: usize) -> usize {
+    left + right
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn it_works() {
+        assert_eq!(super::add(2, 2), 4);
+    }
+}
@@ -0,0 +1,8 @@
+#!/usr/bin/env cargo-eval
+
+//! ```cargo
+//! [lib]
+//! name = "other"
+//! ```
+
+fn main() {}
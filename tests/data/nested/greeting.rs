@@ -0,0 +1,7 @@
+//! Nested file modules are found in `greeting/`
+
+pub mod name;
+
+pub fn greeting() -> String {
+    format!("{} from {}!", super::GREETING, name::NAME)
+}
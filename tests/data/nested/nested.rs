@@ -0,0 +1,12 @@
+---
+[package]
+edition = "2021"
+---
+
+mod greeting;
+
+const GREETING: &str = "Hello";
+
+fn main() {
+    println!("{}", greeting::greeting());
+}
@@ -0,0 +1,6 @@
+#!/usr/bin/env cargo-eval
+
+use std::borrow::Cow;
+
+let elephant: Cow<str> = "a pink elephant!".into();
+panic!("{elephant}");
@@ -0,0 +1,8 @@
+//! ```cargo
+//! [profile.fast]
+//! inherits = "release"
+//! ```
+
+fn main() {
+    println!("debug_assertions: {}", cfg!(debug_assertions));
+}
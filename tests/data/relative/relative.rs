@@ -0,0 +1,11 @@
+---
+[package]
+edition = "2021"
+---
+
+mod helper;
+
+fn main() {
+    println!("{}", helper::greeting());
+    print!("{}", include_str!("data.txt"));
+}
@@ -0,0 +1,13 @@
+#!/usr/bin/env cargo-eval
+
+//! ```cargo
+//! [package]
+//! edition = "2021"
+//!
+//! [dependencies]
+//! helpers = { path = "script_dep/helpers.rs" }
+//! ```
+
+fn main() {
+    println!("{}", helpers::greeting("world"));
+}
@@ -0,0 +1,8 @@
+//! ```cargo
+//! [package]
+//! edition = "2021"
+//! ```
+
+pub fn greeting(name: &str) -> String {
+    format!("Hello, {name}!")
+}
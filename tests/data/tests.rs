@@ -0,0 +1,15 @@
+#[test]
+fn passes() {}
+
+#[test]
+fn passes_too() {
+    println!("captured");
+}
+
+#[test]
+#[ignore]
+fn fails() {
+    panic!("should not run");
+}
+
+fn main() {}
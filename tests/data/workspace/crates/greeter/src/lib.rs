@@ -0,0 +1,3 @@
+pub fn greet() -> &'static str {
+    "Hello from the workspace!"
+}
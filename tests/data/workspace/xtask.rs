@@ -0,0 +1,15 @@
+//! ```cargo
+//! [package]
+//! workspace = true
+//! version.workspace = true
+//! edition.workspace = true
+//!
+//! [dependencies]
+//! greeter.workspace = true
+//! ```
+
+fn main() {
+    println!("{}", greeter::greet());
+    println!("version: {}", env!("CARGO_PKG_VERSION"));
+    println!("debug-assertions: {}", cfg!(debug_assertions));
+}
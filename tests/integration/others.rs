@@ -44,3 +44,433 @@ fn test_clean() {
 
     fixture.close();
 }
+
+#[test]
+fn test_clean_keeps_other_scripts() {
+    let fixture = crate::util::Fixture::new();
+    for script in ["tests/data/hello_world.rs", "tests/data/args.rs"] {
+        fixture.cmd().arg(script).assert().success();
+    }
+
+    fixture
+        .cmd()
+        .args(["--clean", "-Zpolyfill"])
+        .arg("tests/data/hello_world.rs")
+        .assert()
+        .success();
+
+    assert_eq!(binaries(&fixture, "hello_world_"), 0);
+    assert_ne!(binaries(&fixture, "args_"), 0);
+    let workspaces = walk(&fixture.path().join("target/eval"))
+        .into_iter()
+        .filter(|path| path.ends_with("Cargo.toml"))
+        .collect::<Vec<_>>();
+    assert_eq!(workspaces.len(), 1, "{workspaces:?}");
+    assert!(workspaces[0].parent().unwrap().ends_with("args"));
+
+    fixture.close();
+}
+
+#[test]
+fn test_clean_keeps_scripts_with_same_name() {
+    let fixture = crate::util::Fixture::new();
+    let scripts = ["a", "b"].map(|dir| {
+        let script = fixture.path().join(dir).join("hello_world.rs");
+        std::fs::create_dir(script.parent().unwrap()).unwrap();
+        std::fs::copy("tests/data/hello_world.rs", &script).unwrap();
+        fixture.cmd().arg(&script).assert().success();
+        script
+    });
+    let fingerprints = || {
+        std::fs::read_dir(fixture.path().join("target/debug/.fingerprint"))
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("hello_world-")
+            })
+            .count()
+    };
+    assert_eq!(fingerprints(), 2);
+
+    fixture
+        .cmd()
+        .args(["--clean", "-Zpolyfill"])
+        .arg(&scripts[0])
+        .assert()
+        .success();
+
+    assert_eq!(binaries(&fixture, "hello_world_"), 1);
+    assert_eq!(fingerprints(), 1);
+    let workspaces = walk(&fixture.path().join("target/eval"))
+        .into_iter()
+        .filter(|path| path.ends_with("Cargo.toml"))
+        .count();
+    assert_eq!(workspaces, 1);
+
+    fixture.close();
+}
+
+fn walk(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            paths.extend(walk(&path));
+        } else {
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+#[test]
+fn test_gc_missing_source() {
+    let fixture = crate::util::Fixture::new();
+    let kept = fixture.path().join("kept.rs");
+    std::fs::copy("tests/data/hello_world.rs", &kept).unwrap();
+    fixture.cmd().arg(&kept).assert().success();
+    let deleted = fixture.path().join("deleted.rs");
+    std::fs::copy("tests/data/args.rs", &deleted).unwrap();
+    fixture.cmd().arg(&deleted).assert().success();
+    std::fs::remove_file(&deleted).unwrap();
+
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "gc", "--missing-source", "--dry-run"])
+        .assert()
+        .success()
+        .stderr_matches(
+            "    Removing [CWD]/deleted.rs ([..])
+     Summary 1 script, [..] total ([..] remaining)
+warning: aborting gc due to dry run
+",
+        );
+    assert_eq!(binaries(&fixture, "deleted_"), 1);
+
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "gc", "--missing-source"])
+        .assert()
+        .success()
+        .stderr_matches(
+            "    Removing [CWD]/deleted.rs ([..])
+     Removed 1 script, [..] total ([..] remaining)
+",
+        );
+    assert_eq!(binaries(&fixture, "deleted_"), 0);
+    assert_eq!(binaries(&fixture, "kept_"), 1);
+
+    fixture.close();
+}
+
+#[test]
+fn test_gc_keeps_script_directories() {
+    let fixture = crate::util::Fixture::new();
+    let dir = fixture.path().join("scripts/empty");
+    std::fs::create_dir_all(&dir).unwrap();
+    let deleted = dir.join("deleted.rs");
+    std::fs::copy("tests/data/hello_world.rs", &deleted).unwrap();
+    fixture.cmd().arg(&deleted).assert().success();
+    std::fs::remove_file(&deleted).unwrap();
+
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "gc", "--missing-source"])
+        .assert()
+        .success();
+    assert_eq!(binaries(&fixture, "deleted_"), 0);
+    assert!(dir.is_dir());
+
+    fixture.close();
+}
+
+#[test]
+fn test_gc_library() {
+    let fixture = crate::util::Fixture::new();
+    let artifacts = |dir: &str, prefix: &str| {
+        std::fs::read_dir(fixture.path().join("target/debug").join(dir))
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(prefix))
+            .count()
+    };
+    let deleted = fixture.path().join("deleted/lib.rs");
+    std::fs::create_dir(deleted.parent().unwrap()).unwrap();
+    std::fs::copy("tests/data/lib.rs", &deleted).unwrap();
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "--test"])
+        .arg(&deleted)
+        .assert()
+        .success();
+    assert_ne!(artifacts(".fingerprint", "lib-"), 0);
+    assert_ne!(artifacts("deps", "liblib-"), 0);
+    std::fs::remove_file(&deleted).unwrap();
+
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "gc", "--missing-source"])
+        .assert()
+        .success();
+    for (dir, prefix) in [
+        (".fingerprint", "lib-"),
+        ("deps", "lib-"),
+        ("deps", "liblib-"),
+        ("incremental", "lib-"),
+    ] {
+        assert_eq!(artifacts(dir, prefix), 0, "{dir}/{prefix}*");
+    }
+
+    fixture.close();
+}
+
+#[test]
+fn test_gc_libraries_with_same_name() {
+    let fixture = crate::util::Fixture::new();
+    for dir in ["deleted", "kept"] {
+        let dir = fixture.path().join(dir);
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::copy("tests/data/lib.rs", dir.join("lib.rs")).unwrap();
+        fixture
+            .cmd()
+            .args(["-Zpolyfill", "--test"])
+            .arg(dir.join("lib.rs"))
+            .assert()
+            .success();
+    }
+    std::fs::remove_dir_all(fixture.path().join("deleted")).unwrap();
+
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "gc", "--missing-source"])
+        .assert()
+        .success()
+        .stderr_matches(
+            "    Removing [CWD]/deleted/lib.rs ([..])
+     Removed 1 script, [..] total ([..] remaining)
+",
+        );
+
+    // Cargo names libraries after the package, so the last script built owns them
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "--test"])
+        .arg(fixture.path().join("kept/lib.rs"))
+        .assert()
+        .success()
+        .stderr_matches(
+            "[..]Finished [..]
+[..]Running [..]
+[..]Doc-tests lib
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_gc_workspace_target_dir() {
+    let fixture = crate::util::Fixture::new();
+    let cargo_home = fixture.path().join("cargo-home");
+    let workspace = fixture.path().join("workspace");
+    for file in [
+        "Cargo.toml",
+        "crates/greeter/Cargo.toml",
+        "crates/greeter/src/lib.rs",
+        "xtask.rs",
+    ] {
+        let path = workspace.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::copy(
+            std::path::Path::new("tests/data/workspace").join(file),
+            path,
+        )
+        .unwrap();
+    }
+    fixture
+        .cmd()
+        .env_remove("CARGO_TARGET_DIR")
+        .env("CARGO_HOME", &cargo_home)
+        .arg(workspace.join("xtask.rs"))
+        .assert()
+        .success();
+    std::fs::remove_file(workspace.join("xtask.rs")).unwrap();
+
+    fixture
+        .cmd()
+        .env_remove("CARGO_TARGET_DIR")
+        .env("CARGO_HOME", &cargo_home)
+        .args(["-Zpolyfill", "gc", "--missing-source"])
+        .assert()
+        .success()
+        .stderr_matches(
+            "    Removing [CWD]/workspace/xtask.rs ([..])
+     Removed 1 script, [..] total ([..] remaining)
+",
+        );
+    let built = std::fs::read_dir(workspace.join("target/debug"))
+        .unwrap()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("xtask"))
+        .count();
+    assert_eq!(built, 0);
+
+    fixture.close();
+}
+
+#[test]
+fn test_cache_survives_edits() {
+    let fixture = crate::util::Fixture::new();
+    let script = fixture.path().join("edited.rs");
+    std::fs::copy("tests/data/hello_world.rs", &script).unwrap();
+    fixture.cmd().arg(&script).assert().success();
+    let lockfiles = || {
+        walk(&fixture.path().join("target/eval"))
+            .into_iter()
+            .filter(|path| path.ends_with("Cargo.lock"))
+            .collect::<Vec<_>>()
+    };
+    let before = lockfiles();
+    assert_eq!(before.len(), 1, "{before:?}");
+
+    let body = std::fs::read_to_string(&script)
+        .unwrap()
+        .replace("Hello world!", "Hello edit!");
+    std::fs::write(&script, body).unwrap();
+    fixture.cmd().arg(&script).assert().success().stdout_eq(
+        "Hello edit!
+",
+    );
+    assert_eq!(lockfiles(), before);
+    assert_eq!(binaries(&fixture, "edited_"), 1);
+
+    // Identical copies get their own workspace
+    let copy_dir = fixture.path().join("copy");
+    std::fs::create_dir(&copy_dir).unwrap();
+    std::fs::copy(&script, copy_dir.join("edited.rs")).unwrap();
+    fixture
+        .cmd()
+        .arg(copy_dir.join("edited.rs"))
+        .assert()
+        .success();
+    assert_eq!(lockfiles().len(), 2);
+    assert_eq!(binaries(&fixture, "edited_"), 2);
+
+    fixture.close();
+}
+
+#[test]
+fn test_gc_max_size() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .arg("tests/data/hello_world.rs")
+        .assert()
+        .success();
+
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "gc", "--max-size", "1K"])
+        .assert()
+        .success()
+        .stderr_matches(
+            "    Removing [..]/tests/data/hello_world.rs ([..])
+     Removed 1 script, [..] total (0.0B remaining)
+",
+        );
+    assert_eq!(binaries(&fixture, "hello_world_"), 0);
+
+    fixture.close();
+}
+
+#[test]
+fn test_gc_max_age_fresh() {
+    let fixture = crate::util::Fixture::new();
+    let script = fixture.path().join("fresh.rs");
+    std::fs::copy("tests/data/hello_world.rs", &script).unwrap();
+    fixture.cmd().arg(&script).assert().success();
+
+    // Pretend the script was last built long ago
+    let index_path = fixture.path().join("target/eval/index.json");
+    let mut index: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&index_path).unwrap()).unwrap();
+    for entry in index["scripts"].as_object_mut().unwrap().values_mut() {
+        entry["last-used"] = 0.into();
+    }
+    std::fs::write(&index_path, index.to_string()).unwrap();
+
+    fixture
+        .cmd()
+        .env("CARGO_LOG", "cargo_eval::util::fingerprint=debug")
+        .arg(&script)
+        .assert()
+        .success()
+        .stderr_matches(
+            "[..] [CWD]/fresh.rs is fresh
+",
+        );
+
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "gc", "--max-age", "1d"])
+        .assert()
+        .success()
+        .stderr_matches(
+            "     Removed 0 scripts, 0.0B total ([..] remaining)
+",
+        );
+    assert_eq!(binaries(&fixture, "fresh_"), 1);
+
+    fixture.close();
+}
+
+#[test]
+fn test_gc_auto() {
+    let fixture = crate::util::Fixture::new();
+    let deleted = fixture.path().join("deleted.rs");
+    std::fs::copy("tests/data/hello_world.rs", &deleted).unwrap();
+    fixture.cmd().arg(&deleted).assert().success();
+    std::fs::remove_file(&deleted).unwrap();
+
+    fixture
+        .cmd()
+        .env("CARGO_EVAL_GC_AUTO", "true")
+        .arg("tests/data/hello_world.rs")
+        .assert()
+        .success()
+        .stdout_eq(
+            "Hello world!
+",
+        );
+    assert_eq!(binaries(&fixture, "deleted_"), 0);
+    assert_eq!(binaries(&fixture, "hello_world_"), 1);
+
+    fixture.close();
+}
+
+#[test]
+fn test_gc_requires_polyfill() {
+    let fixture = crate::util::Fixture::new();
+    fixture.cmd().arg("gc").assert().failure().stderr_eq(
+        "error: `gc` is unstable and requires `-Zpolyfill`
+",
+    );
+
+    fixture.close();
+}
+
+/// Binaries built into the fixture's target directory
+fn binaries(fixture: &crate::util::Fixture, prefix: &str) -> usize {
+    std::fs::read_dir(fixture.path().join("target/debug"))
+        .unwrap()
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let extension = entry.path().extension().map(|e| e.to_owned());
+            name.starts_with(prefix) && (extension.is_none() || extension.unwrap() == "exe")
+        })
+        .count()
+}
@@ -29,7 +29,7 @@ fn clean_output_with_edition() {
         .stderr_matches(
             "   Compiling has_edition v0.0.0 ([CWD]/target/eval/[..]/has_edition)
     Finished dev [unoptimized + debuginfo] target(s) in [..]s
-     Running `[CWD]/target/debug/has_edition_[..][EXE]`
+     Running `[CWD]/target/eval/[..]/has_edition/bin/debug/has_edition[EXE]`
 ",
         )
         .stdout_eq(
@@ -53,7 +53,7 @@ fn warning_without_edition() {
             "warning: `package.edition` is unspecifiead, defaulting to `2021`
    Compiling no_edition v0.0.0 ([CWD]/target/eval/[..]/no_edition)
     Finished dev [unoptimized + debuginfo] target(s) in [..]s
-     Running `[CWD]/target/debug/no_edition_[..][EXE]`
+     Running `[CWD]/target/eval/[..]/no_edition/bin/debug/no_edition[EXE]`
 ",
         )
         .stdout_eq(
@@ -133,7 +133,7 @@ fn rebuild() {
             "warning: `package.edition` is unspecifiead, defaulting to `2021`
    Compiling cecho v0.0.0 ([CWD]/target/eval/[..]/cecho)
     Finished dev [unoptimized + debuginfo] target(s) in [..]s
-     Running `[CWD]/target/debug/cecho_[..][EXE]`
+     Running `[CWD]/target/eval/[..]/cecho/bin/debug/cecho[EXE]`
 ",
         )
         .stdout_eq(
@@ -151,7 +151,7 @@ fn rebuild() {
         .stderr_matches(
             "warning: `package.edition` is unspecifiead, defaulting to `2021`
     Finished dev [unoptimized + debuginfo] target(s) in [..]s
-     Running `[CWD]/target/debug/cecho_[..][EXE]`
+     Running `[CWD]/target/eval/[..]/cecho/bin/debug/cecho[EXE]`
 ",
         )
         .stdout_eq(
@@ -171,7 +171,7 @@ fn rebuild() {
             "warning: `package.edition` is unspecifiead, defaulting to `2021`
    Compiling cecho v0.0.0 ([CWD]/target/eval/[..]/cecho)
     Finished dev [unoptimized + debuginfo] target(s) in [..]s
-     Running `[CWD]/target/debug/cecho_[..][EXE]`
+     Running `[CWD]/target/eval/[..]/cecho/bin/debug/cecho[EXE]`
 ",
         )
         .stdout_eq(
@@ -215,6 +215,62 @@ stack backtrace:
     fixture.close();
 }
 
+#[test]
+fn test_without_main() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .arg("examples/hello-without-main.ers")
+        .assert()
+        .success()
+        .stdout_eq(
+            "hello, rust
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_panic_without_main() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .env("RUST_BACKTRACE", "0")
+        .arg("tests/data/panic_without_main.rs")
+        .assert()
+        .failure()
+        .stderr_matches(
+            "thread 'main' panicked at 'a pink elephant!', [..]/tests/data/panic_without_main.rs:6:1
+note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_without_main_modules() {
+    let fixture = crate::util::Fixture::new();
+    let dir = fixture.path().join("relative");
+    std::fs::create_dir_all(&dir).unwrap();
+    for file in ["without_main.rs", "helper.rs"] {
+        std::fs::copy(format!("tests/data/relative/{file}"), dir.join(file)).unwrap();
+    }
+
+    fixture
+        .cmd()
+        .arg(dir.join("without_main.rs"))
+        .assert()
+        .success()
+        .stdout_eq(
+            "Hello from helper!
+",
+        );
+
+    fixture.close();
+}
+
 #[test]
 fn test_override_backtrace() {
     let fixture = crate::util::Fixture::new();
@@ -255,6 +311,171 @@ test result: ok. 1 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out; fini
     fixture.close();
 }
 
+#[test]
+fn test_test_filter() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "--test"])
+        .arg("tests/data/tests.rs")
+        .args(["passes_too", "--exact", "--nocapture"])
+        .assert()
+        .success()
+        .stdout_matches(
+            "
+running 1 test
+test passes_too ... captured
+ok
+
+test result: ok. 1 passed; 0 failed; 0 ignored; 0 measured; 2 filtered out; finished in [..]s
+
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_lib() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "--test"])
+        .arg("tests/data/lib.rs")
+        .assert()
+        .success()
+        .stdout_matches(
+            "
+running 1 test
+test tests::it_works ... ok
+
+test result: ok. 1 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out; finished in [..]s
+
+
+running 1 test
+test [..]/tests/data/lib.rs - add (line 8) ... ok
+
+test result: ok. 1 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out; finished in [..]s
+
+",
+        );
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "--test", "--doc"])
+        .arg("tests/data/lib.rs")
+        .assert()
+        .success()
+        .stdout_matches(
+            "
+running 1 test
+test [..]/tests/data/lib.rs - add (line 8) ... ok
+
+test result: ok. 1 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out; finished in [..]s
+
+",
+        );
+    fixture
+        .cmd()
+        .arg("tests/data/lib.rs")
+        .assert()
+        .failure()
+        .stderr_matches(
+            "error: `[..]/tests/data/lib.rs` is a library, so can't be run
+
+help: to run its tests, pass `-Zpolyfill --test`
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_lib_frontmatter() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "--test"])
+        .arg("tests/data/lib_frontmatter.rs")
+        .assert()
+        .success()
+        .stdout_matches(
+            "
+running 1 test
+test tests::it_works ... ok
+
+test result: ok. 1 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out; finished in [..]s
+
+
+running 1 test
+test [..] - add (line 8) ... ok
+
+test result: ok. 1 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out; finished in [..]s
+
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_doc_requires_lib() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "--test", "--doc"])
+        .arg("tests/data/tests.rs")
+        .assert()
+        .failure()
+        .stderr_matches(
+            "warning: `package.edition` is unspecifiead, defaulting to `2021`
+error: `[..]/tests/data/tests.rs` is not a library, so has no doc-tests
+
+help: remove `fn main` and top-level statements, or add an empty `[lib]` to its manifest
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_test_no_run() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "--test", "--no-run"])
+        .arg("tests/data/tests.rs")
+        .assert()
+        .success()
+        .stdout_eq("")
+        .stderr_matches(
+            "warning: `package.edition` is unspecifiead, defaulting to `2021`
+   Compiling tests v0.0.0 ([..])
+    Finished test [unoptimized + debuginfo] target(s) in [..]s
+  Executable unittests [..]tests.rs ([..])
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_no_run_requires_harness() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .arg("--no-run")
+        .arg("tests/data/tests.rs")
+        .assert()
+        .failure()
+        .stderr_matches(
+            "error: the following required arguments were not provided:
+  <--test|--bench>
+...",
+        );
+
+    fixture.close();
+}
+
 #[test]
 fn test_escaped_hyphen_arg() {
     let fixture = crate::util::Fixture::new();
@@ -267,7 +488,7 @@ fn test_escaped_hyphen_arg() {
         .success()
         .stdout_matches(
             r#"
- [0]: "[..]/args_[..]"
+ [0]: "[..]args[..]"
  [1]: "-NotAnArg"
 "#,
         );
@@ -286,7 +507,7 @@ fn test_unescaped_hyphen_arg() {
         .success()
         .stdout_matches(
             r#"
- [0]: "[..]/args_[..]"
+ [0]: "[..]args[..]"
  [1]: "-NotAnArg"
 "#,
         );
@@ -305,7 +526,7 @@ fn test_same_flags() {
         .success()
         .stdout_matches(
             r#"
- [0]: "[..]args_[..]"
+ [0]: "[..]args[..]"
  [1]: "--help"
 "#,
         );
@@ -357,3 +578,1197 @@ fn test_path_dep() {
 
     fixture.close();
 }
+
+#[test]
+fn test_frontmatter() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .arg("tests/data/frontmatter.rs")
+        .assert()
+        .success()
+        .stdout_eq(
+            "Hello world! line: 9
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_frontmatter_relative_paths() {
+    let fixture = crate::util::Fixture::new();
+    let dir = fixture.path().join("relative");
+    std::fs::create_dir_all(&dir).unwrap();
+    for file in ["relative.rs", "helper.rs", "data.txt"] {
+        std::fs::copy(format!("tests/data/relative/{file}"), dir.join(file)).unwrap();
+    }
+
+    fixture
+        .cmd()
+        .arg(dir.join("relative.rs"))
+        .assert()
+        .success()
+        .stdout_eq(
+            "Hello from helper!
+Hello from data.txt!
+",
+        );
+    // Nothing is generated next to the script
+    let mut files = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect::<Vec<_>>();
+    files.sort();
+    assert_eq!(files, ["data.txt", "helper.rs", "relative.rs"]);
+
+    fixture.close();
+}
+
+#[test]
+fn test_frontmatter_nested_modules() {
+    let fixture = crate::util::Fixture::new();
+    let dir = fixture.path().join("nested");
+    std::fs::create_dir_all(dir.join("greeting")).unwrap();
+    for file in ["nested.rs", "greeting.rs", "greeting/name.rs"] {
+        std::fs::copy(format!("tests/data/nested/{file}"), dir.join(file)).unwrap();
+    }
+
+    fixture
+        .cmd()
+        .arg(dir.join("nested.rs"))
+        .assert()
+        .success()
+        .stdout_eq(
+            "Hello from a nested module!
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_attribute() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .arg("tests/data/attribute.rs")
+        .assert()
+        .success()
+        .stdout_eq(
+            "Hello world! line: 9
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_manifest_error() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .arg("tests/data/lib_manifest.rs")
+        .assert()
+        .failure()
+        .stderr_matches(
+            "error: `lib` must be empty in embedded manifests
+ --> [..]/tests/data/lib_manifest.rs:4:6
+  |
+4 | //! [lib]
+  |      ^^^
+  |
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_add_remove() {
+    let fixture = crate::util::Fixture::new();
+    let script = fixture.path().join("script.rs");
+    std::fs::write(
+        &script,
+        r#"#!/usr/bin/env cargo-eval
+//! ```cargo
+//! [dependencies]
+//! ```
+fn main() {}
+"#,
+    )
+    .unwrap();
+    let dep = fixture.path().join("dep");
+    std::fs::create_dir_all(dep.join("src")).unwrap();
+    std::fs::write(
+        dep.join("Cargo.toml"),
+        r#"[package]
+name = "dep"
+version = "0.1.0"
+
+[features]
+foo = []
+"#,
+    )
+    .unwrap();
+    std::fs::write(dep.join("src/lib.rs"), "").unwrap();
+
+    fixture
+        .cmd()
+        .current_dir(fixture.path())
+        .args([
+            "-Zpolyfill",
+            "add",
+            "script.rs",
+            "--path",
+            "dep",
+            "-F",
+            "foo",
+        ])
+        .assert()
+        .success()
+        .stderr_matches(
+            "      Adding dep (local) to dependencies
+",
+        );
+    snapbox::assert_eq(
+        r#"#!/usr/bin/env cargo-eval
+//! ```cargo
+//! [dependencies]
+//! dep = { path = "dep", features = ["foo"] }
+//! ```
+fn main() {}
+"#,
+        std::fs::read_to_string(&script).unwrap(),
+    );
+
+    fixture
+        .cmd()
+        .current_dir(fixture.path())
+        .args(["-Zpolyfill", "remove", "script.rs", "dep"])
+        .assert()
+        .success()
+        .stderr_matches(
+            "    Removing dep from dependencies
+",
+        );
+    snapbox::assert_eq(
+        r#"#!/usr/bin/env cargo-eval
+//! ```cargo
+//! ```
+fn main() {}
+"#,
+        std::fs::read_to_string(&script).unwrap(),
+    );
+
+    fixture.close();
+}
+
+#[test]
+fn test_add_requires_polyfill() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .args([
+            "add",
+            "tests/data/hello_world.rs",
+            "--path",
+            "tests/data/path_dep",
+        ])
+        .assert()
+        .failure()
+        .stderr_matches(
+            "error: `add` is unstable and requires `-Zpolyfill`
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_expr() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .args(["-Zeval", "-e", "1 + 2"])
+        .assert()
+        .success()
+        .stdout_eq(
+            "3
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_expr_requires_eval() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .args(["-e", "1 + 2"])
+        .assert()
+        .failure()
+        .stderr_matches(
+            "error: `--expr` is unstable and requires `-Zeval`
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_loop() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .args([
+            "-Zloop",
+            "--begin",
+            "let mut total = 0;",
+            "--end",
+            r#"println!("total: {total}")"#,
+            "-l",
+            r#"|line, n| { total += line.len(); println!("{n}: {line}"); }"#,
+        ])
+        .stdin("a\nbb\r\nccc")
+        .assert()
+        .success()
+        .stdout_eq(
+            "1: a
+2: bb
+3: ccc
+total: 6
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_loop_prints_output() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .args(["-Zloop", "-l", "|line, _| line.len()"])
+        .stdin("a\nbb\n")
+        .assert()
+        .success()
+        .stdout_eq(
+            "1
+2
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_loop_prints_display_output() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .args(["-Zloop", "-l", "|line, _| line.to_uppercase()"])
+        .stdin("a \"b\"\nc\n")
+        .assert()
+        .success()
+        .stdout_eq(
+            r#"A "B"
+C
+"#,
+        );
+    fixture
+        .cmd()
+        .args(["-Zloop", "-l", "|line, _| line.parse::<u8>().ok()"])
+        .stdin("1\nc\n")
+        .assert()
+        .success()
+        .stdout_eq(
+            "Some(1)
+None
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_loop_requires_loop() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .args(["-l", "|line, _| line.len()"])
+        .assert()
+        .failure()
+        .stderr_matches(
+            "error: `--loop` is unstable and requires `-Zloop`
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_locked_without_lockfile() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .arg("--locked")
+        .arg("tests/data/hello_world.rs")
+        .assert()
+        .failure()
+        .stderr_matches(
+            "error: the lock file [..]/hello_world/Cargo.lock needs to be updated but --locked was passed to prevent this
+...",
+        );
+
+    fixture
+        .cmd()
+        .arg("tests/data/hello_world.rs")
+        .assert()
+        .success();
+
+    fixture
+        .cmd()
+        .arg("--locked")
+        .arg("tests/data/hello_world.rs")
+        .assert()
+        .success()
+        .stdout_eq(
+            "Hello world!
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_offline() {
+    let fixture = crate::util::Fixture::new();
+    let script = fixture.path().join("script.rs");
+    std::fs::write(
+        &script,
+        r#"//! ```cargo
+//! [dependencies]
+//! cargo-eval-no-such-crate = "1"
+//! ```
+fn main() {}
+"#,
+    )
+    .unwrap();
+
+    fixture
+        .cmd()
+        .arg("--offline")
+        .arg(&script)
+        .env("CARGO_HOME", fixture.path().join("cargo_home"))
+        .assert()
+        .failure()
+        .stderr_matches(
+            "error: no matching package named `cargo-eval-no-such-crate` found
+...",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_features() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .arg("tests/data/features.rs")
+        .assert()
+        .success()
+        .stdout_eq(
+            "dflt: true, cli: false, shebang: true, other: false
+",
+        );
+
+    fixture
+        .cmd()
+        .args(["--features", "cli", "--no-default-features"])
+        .arg("tests/data/features.rs")
+        .assert()
+        .success()
+        .stdout_eq(
+            "dflt: false, cli: true, shebang: true, other: false
+",
+        );
+
+    fixture
+        .cmd()
+        .arg("--all-features")
+        .arg("tests/data/features.rs")
+        .assert()
+        .success()
+        .stdout_eq(
+            "dflt: true, cli: true, shebang: true, other: true
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_profile() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .arg("tests/data/profile.rs")
+        .assert()
+        .success()
+        .stdout_eq(
+            "debug_assertions: true
+",
+        );
+
+    fixture
+        .cmd()
+        .args(["--profile", "fast"])
+        .arg("tests/data/profile.rs")
+        .assert()
+        .success()
+        .stdout_eq(
+            "debug_assertions: false
+",
+        );
+
+    fixture
+        .cmd()
+        .arg("--release")
+        .arg("tests/data/profile.rs")
+        .assert()
+        .success()
+        .stdout_eq(
+            "debug_assertions: false
+",
+        );
+
+    fixture
+        .cmd()
+        .args(["--profile", "nope"])
+        .arg("tests/data/profile.rs")
+        .assert()
+        .failure()
+        .stderr_matches(
+            "error: profile `nope` is not defined
+
+available profiles: bench, dev, doc, fast, release, test
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_target() {
+    let host = std::process::Command::new("rustc")
+        .arg("-vV")
+        .output()
+        .unwrap();
+    let host = String::from_utf8(host.stdout).unwrap();
+    let host = host
+        .lines()
+        .find_map(|l| l.strip_prefix("host: "))
+        .unwrap()
+        .to_owned();
+    let runner_env = format!(
+        "CARGO_TARGET_{}_RUNNER",
+        host.to_uppercase().replace(['-', '.'], "_")
+    );
+
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .args(["--target", &host])
+        .arg("tests/data/hello_world.rs")
+        .assert()
+        .success()
+        .stdout_eq(
+            "Hello world!
+",
+        );
+
+    fixture
+        .cmd()
+        .args(["--target", &host])
+        .arg("tests/data/hello_world.rs")
+        .env(runner_env, "echo runner")
+        .assert()
+        .success()
+        .stdout_matches(format!(
+            "runner [CWD]/target/eval/[..]/hello_world/bin/{host}/debug/hello_world[EXE]
+"
+        ));
+
+    fixture.close();
+}
+
+#[test]
+fn test_config() {
+    let fixture = crate::util::Fixture::new();
+    let target_dir = fixture.path().join("custom-target");
+    fixture
+        .cmd()
+        .env_remove("CARGO_TARGET_DIR")
+        .arg("--config")
+        .arg(format!(
+            "build.target-dir={:?}",
+            target_dir.display().to_string()
+        ))
+        .arg("tests/data/hello_world.rs")
+        .assert()
+        .success()
+        .stdout_eq(
+            "Hello world!
+",
+        );
+    assert!(target_dir.join("eval").exists());
+
+    let config_path = fixture.path().join("config.toml");
+    std::fs::write(&config_path, "[profile.dev]\ndebug-assertions = false\n").unwrap();
+    fixture
+        .cmd()
+        .arg("--config")
+        .arg(&config_path)
+        .arg("tests/data/profile.rs")
+        .assert()
+        .success()
+        .stdout_eq(
+            "debug_assertions: false
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_color() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .args(["--color", "always", "--profile", "nope"])
+        .arg("tests/data/hello_world.rs")
+        .assert()
+        .failure()
+        .stderr_matches(
+            "\u{1b}[..]error[..] profile `nope` is not defined
+...",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_manifest() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "--manifest"])
+        .arg("tests/data/path_dep.rs")
+        .assert()
+        .success()
+        .stdout_matches(
+            r#"[[bin]]
+name = "path_dep_[..]"
+path = "[..]/tests/data/path_dep.rs"
+
+[dependencies.path_dep]
+path = "[..]/tests/data/path_dep"
+
+[package]
+edition = "2021"
+name = "path_dep"
+publish = false
+version = "0.0.0"
+
+[profile.release]
+strip = true
+
+[workspace]
+"#,
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_manifest_json() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "--manifest", "--format", "json"])
+        .arg("tests/data/hello_world.rs")
+        .assert()
+        .success()
+        .stdout_matches(
+            r#"{
+  "bin": [
+    {
+      "name": "hello_world_[..]",
+      "path": "[..]/tests/data/hello_world.rs"
+    }
+  ],
+  "package": {
+    "edition": "2021",
+    "name": "hello_world",
+    "publish": false,
+    "version": "0.0.0"
+  },
+  "profile": {
+    "release": {
+      "strip": true
+    }
+  },
+  "workspace": {}
+}
+"#,
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_manifest_requires_polyfill() {
+    let fixture = crate::util::Fixture::new();
+    fixture
+        .cmd()
+        .arg("--manifest")
+        .arg("tests/data/hello_world.rs")
+        .assert()
+        .failure()
+        .stderr_eq(
+            "error: `--manifest` is unstable and requires `-Zpolyfill`
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_fresh_skips_cargo() {
+    let fixture = crate::util::Fixture::new();
+    let script = fixture.path().join("fresh.rs");
+    std::fs::copy("tests/data/hello_world.rs", &script).unwrap();
+    let log = "cargo_eval::util::fingerprint=debug";
+
+    fixture
+        .cmd()
+        .env("CARGO_LOG", log)
+        .arg(&script)
+        .assert()
+        .success()
+        .stdout_eq(
+            "Hello world!
+",
+        )
+        .stderr_eq("");
+    fixture
+        .cmd()
+        .env("CARGO_LOG", log)
+        .arg(&script)
+        .arg("ignored")
+        .assert()
+        .success()
+        .stdout_eq(
+            "Hello world!
+",
+        )
+        .stderr_matches(
+            "[..] [CWD]/fresh.rs is fresh
+",
+        );
+    // Build flags always go through cargo
+    fixture
+        .cmd()
+        .env("CARGO_LOG", log)
+        .arg("--all-features")
+        .arg(&script)
+        .assert()
+        .success()
+        .stderr_eq("");
+
+    let body = std::fs::read_to_string(&script)
+        .unwrap()
+        .replace("Hello world!", "Hello edit!");
+    std::fs::write(&script, body).unwrap();
+    fixture
+        .cmd()
+        .env("CARGO_LOG", log)
+        .arg(&script)
+        .assert()
+        .success()
+        .stdout_eq(
+            "Hello edit!
+",
+        )
+        .stderr_matches(
+            "[..] [CWD]/fresh.rs is stale
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_fresh_config_target_dir() {
+    let fixture = crate::util::Fixture::new();
+    let script = fixture.path().join("fresh.rs");
+    std::fs::copy("tests/data/hello_world.rs", &script).unwrap();
+    std::fs::create_dir_all(fixture.path().join(".cargo")).unwrap();
+    std::fs::write(
+        fixture.path().join(".cargo/config.toml"),
+        "[build]
+target-dir = \"custom-target\"
+",
+    )
+    .unwrap();
+    let log = "cargo_eval::util::fingerprint=debug";
+
+    fixture
+        .cmd()
+        .env_remove("CARGO_TARGET_DIR")
+        .current_dir(fixture.path())
+        .arg(&script)
+        .assert()
+        .success()
+        .stdout_eq(
+            "Hello world!
+",
+        );
+    fixture
+        .cmd()
+        .env_remove("CARGO_TARGET_DIR")
+        .env("CARGO_LOG", log)
+        .current_dir(fixture.path())
+        .arg(&script)
+        .assert()
+        .success()
+        .stderr_matches(
+            "[..] [CWD]/fresh.rs is fresh
+",
+        );
+    assert!(fixture.path().join("custom-target/eval").exists());
+
+    fixture.close();
+}
+
+#[test]
+fn test_fresh_modules() {
+    let fixture = crate::util::Fixture::new();
+    let script = fixture.path().join("modules.rs");
+    std::fs::write(
+        &script,
+        "mod helper;
+
+fn main() {
+    helper::greet();
+    print!(\"{}\", include_str!(\"data.txt\"));
+}
+",
+    )
+    .unwrap();
+    let helper = fixture.path().join("helper.rs");
+    let data = fixture.path().join("data.txt");
+    std::fs::write(&helper, "pub fn greet() { println!(\"Hello world!\"); }\n").unwrap();
+    std::fs::write(&data, "data\n").unwrap();
+    let log = "cargo_eval::util::fingerprint=debug";
+
+    fixture.cmd().arg(&script).assert().success().stdout_eq(
+        "Hello world!
+data
+",
+    );
+
+    std::fs::write(&helper, "pub fn greet() { println!(\"Hello edit!\"); }\n").unwrap();
+    fixture
+        .cmd()
+        .env("CARGO_LOG", log)
+        .arg(&script)
+        .assert()
+        .success()
+        .stdout_eq(
+            "Hello edit!
+data
+",
+        )
+        .stderr_matches(
+            "[..] [CWD]/modules.rs is stale
+",
+        );
+
+    std::fs::write(&data, "edited data\n").unwrap();
+    fixture
+        .cmd()
+        .env("CARGO_LOG", log)
+        .arg(&script)
+        .assert()
+        .success()
+        .stdout_eq(
+            "Hello edit!
+edited data
+",
+        )
+        .stderr_matches(
+            "[..] [CWD]/modules.rs is stale
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_fresh_path_dep() {
+    let fixture = crate::util::Fixture::new();
+    let script = fixture.path().join("path_dep.rs");
+    std::fs::copy("tests/data/path_dep.rs", &script).unwrap();
+    let dep = fixture.path().join("path_dep");
+    std::fs::create_dir_all(dep.join("src")).unwrap();
+    std::fs::copy("tests/data/path_dep/Cargo.toml", dep.join("Cargo.toml")).unwrap();
+    std::fs::copy("tests/data/path_dep/src/lib.rs", dep.join("src/lib.rs")).unwrap();
+
+    fixture.cmd().arg(&script).assert().success().stdout_eq(
+        "Hello world!
+",
+    );
+
+    // Ensure the edit gets a newer mtime, even on coarse filesystems
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    let lib = std::fs::read_to_string(dep.join("src/lib.rs"))
+        .unwrap()
+        .replace("Hello world!", "Hello dep!");
+    std::fs::write(dep.join("src/lib.rs"), lib).unwrap();
+    fixture.cmd().arg(&script).assert().success().stdout_eq(
+        "Hello dep!
+",
+    );
+
+    fixture.close();
+}
+
+#[test]
+fn test_script_dependency() {
+    let fixture = crate::util::Fixture::new();
+    let script = fixture.path().join("script_dep.rs");
+    std::fs::copy("tests/data/script_dep.rs", &script).unwrap();
+    let helpers = fixture.path().join("script_dep/helpers.rs");
+    std::fs::create_dir_all(helpers.parent().unwrap()).unwrap();
+    std::fs::copy("tests/data/script_dep/helpers.rs", &helpers).unwrap();
+
+    fixture.cmd().arg(&script).assert().success().stdout_eq(
+        "Hello, world!
+",
+    );
+
+    // Ensure the edit gets a newer mtime, even on coarse filesystems
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    let lib = std::fs::read_to_string(&helpers)
+        .unwrap()
+        .replace("Hello", "Goodbye");
+    std::fs::write(&helpers, lib).unwrap();
+    fixture.cmd().arg(&script).assert().success().stdout_eq(
+        "Goodbye, world!
+",
+    );
+
+    fixture.close();
+}
+
+#[test]
+fn test_script_dependency_requires_lib() {
+    let fixture = crate::util::Fixture::new();
+    let script = fixture.path().join("script_dep.rs");
+    std::fs::copy("tests/data/script_dep.rs", &script).unwrap();
+    let helpers = fixture.path().join("script_dep/helpers.rs");
+    std::fs::create_dir_all(helpers.parent().unwrap()).unwrap();
+    std::fs::copy("tests/data/hello_world.rs", &helpers).unwrap();
+
+    fixture
+        .cmd()
+        .arg(&script)
+        .assert()
+        .failure()
+        .stderr_matches(
+            "error: `[CWD]/script_dep/helpers.rs` is not a library, so can't be depended on
+
+help: remove `fn main` and top-level statements, or add an empty `[lib]` to its manifest
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_workspace() {
+    let fixture = crate::util::Fixture::new();
+    let workspace = fixture.path().join("workspace");
+    for file in [
+        "Cargo.toml",
+        "crates/greeter/Cargo.toml",
+        "crates/greeter/src/lib.rs",
+        "xtask.rs",
+    ] {
+        let path = workspace.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::copy(
+            std::path::Path::new("tests/data/workspace").join(file),
+            path,
+        )
+        .unwrap();
+    }
+
+    // Inherits from the workspace, building in its target directory
+    fixture
+        .cmd()
+        .env_remove("CARGO_TARGET_DIR")
+        .env("CARGO_HOME", fixture.path().join("cargo-home"))
+        .arg(workspace.join("xtask.rs"))
+        .assert()
+        .success()
+        .stdout_eq(
+            "Hello from the workspace!
+version: 1.2.3
+debug-assertions: false
+",
+        );
+    assert!(workspace.join("target/eval").exists());
+
+    fixture.close();
+}
+
+#[test]
+fn test_workspace_locked() {
+    let fixture = crate::util::Fixture::new();
+    let workspace = fixture.path().join("workspace");
+    for file in [
+        "Cargo.toml",
+        "crates/greeter/Cargo.toml",
+        "crates/greeter/src/lib.rs",
+        "xtask.rs",
+    ] {
+        let path = workspace.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::copy(
+            std::path::Path::new("tests/data/workspace").join(file),
+            path,
+        )
+        .unwrap();
+    }
+    let run = |args: &[&str]| {
+        fixture
+            .cmd()
+            .env_remove("CARGO_TARGET_DIR")
+            .env("CARGO_HOME", fixture.path().join("cargo-home"))
+            .args(args)
+            .arg(workspace.join("xtask.rs"))
+            .assert()
+    };
+    run(&[]).success();
+
+    // Checked against what the script was resolved with, rather than the workspace's changes
+    std::fs::write(workspace.join("Cargo.lock"), "version = 3\n").unwrap();
+    run(&["-v", "--locked"]).success();
+    run(&["-v", "--frozen"]).success();
+
+    fixture.close();
+}
+
+#[test]
+fn test_workspace_requires_workspace() {
+    let fixture = crate::util::Fixture::new();
+    let script = fixture.path().join("xtask.rs");
+    std::fs::copy("tests/data/workspace/xtask.rs", &script).unwrap();
+
+    fixture
+        .cmd()
+        .arg(&script)
+        .assert()
+        .failure()
+        .stderr_matches(
+            "error: `package.workspace = true` requires `[CWD]/xtask.rs` to be in a cargo workspace
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+fn test_workspace_requires_member() {
+    let fixture = crate::util::Fixture::new();
+    let script = fixture.path().join("tools/xtask.rs");
+    std::fs::create_dir_all(script.parent().unwrap()).unwrap();
+    std::fs::copy("tests/data/workspace/xtask.rs", &script).unwrap();
+
+    std::fs::write(
+        fixture.path().join("Cargo.toml"),
+        "[workspace]
+members = [\"crates/*\"]
+",
+    )
+    .unwrap();
+    fixture
+        .cmd()
+        .arg(&script)
+        .assert()
+        .failure()
+        .stderr_matches(
+            "error: `[CWD]/tools/xtask.rs` is not a member of the cargo workspace at `[CWD]`
+
+help: add `tools` to `workspace.members`, or to `workspace.exclude` to keep it out
+",
+        );
+
+    std::fs::write(
+        fixture.path().join("Cargo.toml"),
+        "[workspace]
+members = [\"crates/*\"]
+exclude = [\"tools\"]
+",
+    )
+    .unwrap();
+    fixture
+        .cmd()
+        .arg(&script)
+        .assert()
+        .failure()
+        .stderr_matches(
+            "error: `package.workspace = true` requires `[CWD]/tools/xtask.rs` to be in a cargo workspace
+",
+        );
+
+    std::fs::write(
+        fixture.path().join("Cargo.toml"),
+        "[workspace]
+members = [\"crates/*\", \"tool*\"]
+
+[workspace.package]
+version = \"1.2.3\"
+edition = \"2021\"
+
+[workspace.dependencies]
+greeter = \"1\"
+",
+    )
+    .unwrap();
+    fixture
+        .cmd()
+        .args(["-Zpolyfill", "--manifest"])
+        .arg(&script)
+        .assert()
+        .success();
+
+    fixture.close();
+}
+
+#[test]
+fn test_script_env() {
+    let fixture = crate::util::Fixture::new();
+    let script = fixture.path().join("env.rs");
+    std::fs::copy("tests/data/env.rs", &script).unwrap();
+    let expected = |profile: &str| {
+        format!(
+            "CARGO_EVAL_SCRIPT: [CWD]/env.rs
+CARGO_EVAL_SCRIPT_DIR: [CWD]
+CARGO_EVAL_SCRIPT_HASH: [..]
+CARGO_EVAL_PROFILE: {profile}
+CARGO_EVAL_STDIN: <unset>
+"
+        )
+    };
+
+    // Built through cargo, then run directly, without leaking the caller's variables
+    for _ in 0..2 {
+        fixture
+            .cmd()
+            .env("CARGO_EVAL_STDIN", "1")
+            .arg(&script)
+            .assert()
+            .success()
+            .stdout_matches(expected("dev"));
+    }
+    fixture
+        .cmd()
+        .arg("--release")
+        .arg(&script)
+        .assert()
+        .success()
+        .stdout_matches(expected("release"));
+    fixture
+        .cmd()
+        .arg("-")
+        .stdin(std::fs::read_to_string("tests/data/env.rs").unwrap())
+        .assert()
+        .success()
+        .stdout_matches(
+            "CARGO_EVAL_SCRIPT: [CWD]/target/eval/[..]/stdin.rs
+CARGO_EVAL_SCRIPT_DIR: [CWD]/target/eval/[..]
+CARGO_EVAL_SCRIPT_HASH: [..]
+CARGO_EVAL_PROFILE: dev
+CARGO_EVAL_STDIN: 1
+",
+        );
+
+    fixture.close();
+}
+
+#[test]
+#[cfg(unix)]
+fn test_arg0() {
+    let fixture = crate::util::Fixture::new();
+    let log = "cargo_eval::util::fingerprint=debug";
+    for dir in ["a", "b"] {
+        std::fs::create_dir(fixture.path().join(dir)).unwrap();
+        std::fs::copy(
+            "tests/data/arg0.rs",
+            fixture.path().join(dir).join("arg0.rs"),
+        )
+        .unwrap();
+    }
+
+    // Scripts with the same name are kept apart, both through cargo and when run directly
+    for (dir, stderr) in [
+        ("a", ""),
+        ("b", ""),
+        ("a", "[..] [CWD]/a/arg0.rs is fresh\n"),
+    ] {
+        fixture
+            .cmd()
+            .env("CARGO_LOG", log)
+            .arg(fixture.path().join(dir).join("arg0.rs"))
+            .assert()
+            .success()
+            .stdout_matches(format!(
+                "arg0: [CWD]/{dir}/arg0.rs
+exe: arg0
+"
+            ))
+            .stderr_matches(stderr);
+    }
+
+    fixture.close();
+}
+
+#[test]
+#[cfg(unix)]
+fn test_signal_exit() {
+    let fixture = crate::util::Fixture::new();
+    let script = fixture.path().join("abort.rs");
+    std::fs::copy("tests/data/abort.rs", &script).unwrap();
+
+    // Built through cargo, then run directly
+    for _ in 0..2 {
+        fixture
+            .cmd()
+            .arg(&script)
+            .assert()
+            .interrupted()
+            .stdout_eq(
+                "Aborting
+",
+            )
+            .stderr_eq("");
+    }
+
+    fixture.close();
+}